@@ -0,0 +1,534 @@
+use cgmath::{InnerSpace, VectorSpace};
+
+use crate::mesh::{self, GltfError};
+use crate::scene;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    /// Every keyframe stores an in-tangent, a value and an out-tangent
+    CubicSpline,
+}
+
+/// The node property an animation channel drives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    /// Morph target weights
+    Weights,
+}
+
+/// Keyframes of a glTF animation sampler.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sampler {
+    /// Keyframe times in seconds, strictly increasing
+    pub input: Vec<f32>,
+    /// Keyframe values, `components` floats per value
+    pub output: Vec<f32>,
+    pub interpolation: Interpolation,
+    /// Floats per value, 3 for translation, 4 for rotation and one per morph target for weights
+    pub components: usize,
+}
+
+impl Sampler {
+    fn from_gltf(gltf: &mesh::GltfDocument, sampler: &json::JsonValue, object: &str, property: Property) -> Result<Self, GltfError> {
+        let input_accessor = mesh::required_usize(sampler, object, "input")?;
+        let output_accessor = mesh::required_usize(sampler, object, "output")?;
+        let interpolation = match sampler["interpolation"].as_str() {
+            None | Some("LINEAR") => Interpolation::Linear,
+            Some("STEP") => Interpolation::Step,
+            Some("CUBICSPLINE") => Interpolation::CubicSpline,
+            Some(_) => return Err(GltfError::MissingProperty { object: object.to_string(), property: "interpolation" }),
+        };
+
+        let input = mesh::read_accessor_f32::<1>(gltf, input_accessor)?.into_iter().map(|[time]| time).collect::<Vec<_>>();
+        if input.is_empty() {
+            return Err(GltfError::Accessor { index: input_accessor, reason: "animation input has no keyframes".to_string() });
+        }
+        if input.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(GltfError::Accessor { index: input_accessor, reason: "animation input times must be strictly increasing".to_string() });
+        }
+        let output = match property {
+            Property::Translation | Property::Scale => mesh::read_accessor_f32::<3>(gltf, output_accessor)?.concat(),
+            Property::Rotation => mesh::read_accessor_f32::<4>(gltf, output_accessor)?.concat(),
+            Property::Weights => mesh::read_accessor_f32::<1>(gltf, output_accessor)?.concat(),
+        };
+
+        // Cubic splines store three values per keyframe
+        let values_per_key = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        let components = match property {
+            Property::Translation | Property::Scale => 3,
+            Property::Rotation => 4,
+            // Weights are a flat list, one per morph target for each keyframe
+            Property::Weights => output.len() / (input.len() * values_per_key),
+        };
+        if components == 0 || output.len() != input.len() * values_per_key * components {
+            return Err(GltfError::Accessor { index: output_accessor, reason: format!("has {} values for {} keyframes", output.len(), input.len()) });
+        }
+
+        Ok(Self {
+            input,
+            output,
+            interpolation,
+            components,
+        })
+    }
+
+    /// Value of the i:th keyframe, for cubic splines `part` picks the in-tangent (0), the value (1) or the out-tangent (2).
+    fn key(&self, i: usize, part: usize) -> &[f32] {
+        let start = match self.interpolation {
+            Interpolation::CubicSpline => (i * 3 + part) * self.components,
+            _ => i * self.components,
+        };
+        &self.output[start..start + self.components]
+    }
+
+    /// Samples the value at `time`, clamping to the first and last keyframe.
+    pub fn sample(&self, time: f32, property: Property) -> Vec<f32> {
+        let value_part = if self.interpolation == Interpolation::CubicSpline { 1 } else { 0 };
+        let last = self.input.len() - 1;
+        if time <= self.input[0] {
+            return self.key(0, value_part).to_vec();
+        }
+        if time >= self.input[last] {
+            return self.key(last, value_part).to_vec();
+        }
+
+        // Index of the keyframe at or before time
+        let k = self.input.partition_point(|key_time| *key_time <= time) - 1;
+        let delta = self.input[k + 1] - self.input[k];
+        let t = (time - self.input[k]) / delta;
+
+        let mut value = match self.interpolation {
+            Interpolation::Step => self.key(k, 0).to_vec(),
+            Interpolation::Linear if property == Property::Rotation => {
+                let [a, b] = [k, k + 1].map(|i| quaternion(self.key(i, 0)));
+                let q = a.slerp(b, t);
+                vec![q.v.x, q.v.y, q.v.z, q.s]
+            }
+            Interpolation::Linear => self.key(k, 0).iter().zip(self.key(k + 1, 0)).map(|(a, b)| a + (b - a) * t).collect(),
+            Interpolation::CubicSpline => {
+                // Hermite spline, the tangents are scaled by the keyframe delta as the spec requires
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let value_0 = self.key(k, 1);
+                let out_tangent_0 = self.key(k, 2);
+                let in_tangent_1 = self.key(k + 1, 0);
+                let value_1 = self.key(k + 1, 1);
+                (0..self.components).map(|c| {
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * value_0[c]
+                        + delta * (t3 - 2.0 * t2 + t) * out_tangent_0[c]
+                        + (-2.0 * t3 + 3.0 * t2) * value_1[c]
+                        + delta * (t3 - t2) * in_tangent_1[c]
+                }).collect()
+            }
+        };
+        if property == Property::Rotation {
+            let q = quaternion(&value).normalize();
+            value = vec![q.v.x, q.v.y, q.v.z, q.s];
+        }
+        value
+    }
+}
+
+/// glTF stores quaternions as x, y, z, w
+fn quaternion(xyzw: &[f32]) -> cgmath::Quaternion<f32> {
+    cgmath::Quaternion::new(xyzw[3], xyzw[0], xyzw[1], xyzw[2])
+}
+
+/// Connects a sampler to the node property it animates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    /// Index into `Animation::samplers`, which only holds the samplers channels use
+    pub sampler: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+    pub samplers: Vec<Sampler>,
+    /// Time of the last keyframe of any sampler
+    pub duration: f32,
+}
+
+impl Animation {
+    pub(crate) fn from_gltf(gltf: &mesh::GltfDocument, index: usize, node_count: usize) -> Result<Self, GltfError> {
+        let animation = &gltf.json["animations"][index];
+        let animation_error = |reason: String| GltfError::Animation { index, reason };
+
+        let mut channels = Vec::new();
+        let mut sampler_properties = vec![None; animation["samplers"].len()];
+        for (channel_index, channel) in animation["channels"].members().enumerate() {
+            let object = format!("animations[{}].channels[{}]", index, channel_index);
+            let sampler = mesh::required_usize(channel, &object, "sampler")?;
+            let target = &channel["target"];
+            let target_object = format!("{}.target", object);
+            let property = match mesh::required_str(target, &target_object, "path")? {
+                "translation" => Property::Translation,
+                "rotation" => Property::Rotation,
+                "scale" => Property::Scale,
+                "weights" => Property::Weights,
+                // Extensions such as KHR_animation_pointer add paths we can't play, skip them
+                _ => continue,
+            };
+            // Channels without a node are for extensions to fill in
+            let Some(node) = target["node"].as_usize() else { continue };
+            if node >= node_count {
+                return Err(animation_error(format!("channel {} targets node {} which does not exist", channel_index, node)));
+            }
+            match sampler_properties.get_mut(sampler) {
+                None => return Err(animation_error(format!("channel {} uses sampler {} which does not exist", channel_index, sampler))),
+                // Sampler output is laid out for the property it animates, so a sampler can only drive one kind of property
+                Some(Some(existing)) if *existing != property => return Err(animation_error(format!("sampler {} animates both {:?} and {:?}", sampler, existing, property))),
+                Some(slot) => *slot = Some(property),
+            }
+            channels.push(Channel { node, property, sampler });
+        }
+
+        // Samplers no channel uses, like those of skipped extension channels, may have outputs we can't read, so they're left out
+        let mut samplers = Vec::new();
+        let mut loaded_samplers = vec![None; sampler_properties.len()];
+        for (sampler_index, sampler) in animation["samplers"].members().enumerate() {
+            let Some(property) = sampler_properties[sampler_index] else { continue };
+            let object = format!("animations[{}].samplers[{}]", index, sampler_index);
+            loaded_samplers[sampler_index] = Some(samplers.len());
+            samplers.push(Sampler::from_gltf(gltf, sampler, &object, property)?);
+        }
+        for channel in &mut channels {
+            // Every channel's sampler got a property above, so it was loaded
+            channel.sampler = loaded_samplers[channel.sampler].unwrap();
+        }
+        let duration = samplers.iter().map(|sampler| *sampler.input.last().unwrap()).fold(0.0, f32::max);
+
+        Ok(Self {
+            name: animation["name"].as_str().map(String::from),
+            channels,
+            samplers,
+            duration,
+        })
+    }
+
+    /// Samples every channel at `time` and writes the values into `pose`. Returns the node and property of every
+    /// channel, so callers know which parts of the pose are animated.
+    pub fn apply(&self, time: f32, pose: &mut [NodePose]) -> Vec<(usize, Property)> {
+        for channel in &self.channels {
+            let value = self.samplers[channel.sampler].sample(time, channel.property);
+            let node = &mut pose[channel.node];
+            match channel.property {
+                Property::Translation => node.translation = cgmath::vec3(value[0], value[1], value[2]),
+                Property::Rotation => node.rotation = quaternion(&value),
+                Property::Scale => node.scale = cgmath::vec3(value[0], value[1], value[2]),
+                Property::Weights => node.weights = value,
+            }
+        }
+        self.channels.iter().map(|channel| (channel.node, channel.property)).collect()
+    }
+}
+
+/// The animatable properties of a node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodePose {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    pub weights: Vec<f32>,
+}
+
+impl NodePose {
+    fn from_node(node: &scene::Node) -> Self {
+        Self {
+            translation: node.translation,
+            rotation: node.rotation,
+            scale: node.scale,
+            weights: node.weights.clone(),
+        }
+    }
+
+    /// Copies `property` of this pose into `node`.
+    fn write_property(&self, node: &mut scene::Node, property: Property) {
+        match property {
+            Property::Translation => node.translation = self.translation,
+            Property::Rotation => node.rotation = self.rotation,
+            Property::Scale => node.scale = self.scale,
+            Property::Weights => node.weights = self.weights.clone(),
+        }
+    }
+
+    /// Blends towards `other`, `amount` 0 keeps self and 1 gives other.
+    fn blend(&self, other: &Self, amount: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, amount),
+            rotation: self.rotation.slerp(other.rotation, amount),
+            scale: self.scale.lerp(other.scale, amount),
+            weights: if self.weights.len() == other.weights.len() {
+                self.weights.iter().zip(&other.weights).map(|(a, b)| a + (b - a) * amount).collect()
+            } else {
+                // Nothing to blend between, take whichever side dominates
+                if amount < 0.5 { self.weights.clone() } else { other.weights.clone() }
+            },
+        }
+    }
+}
+
+/// Playback position in one animation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clip {
+    /// Index into `Scene::animations`
+    pub animation: usize,
+    pub time: f32,
+}
+
+/// Plays the animations of a scene, optionally blending a second clip on top of the first.
+pub struct AnimationPlayer {
+    clip: Clip,
+    /// Second clip and how much of it is blended in, 0 to 1
+    blend: Option<(Clip, f32)>,
+    playing: bool,
+    looping: bool,
+    speed: f32,
+    /// Node properties before any animation was applied, a property only one of the blended clips animates blends with these
+    rest_pose: Vec<NodePose>,
+}
+
+impl AnimationPlayer {
+    /// Starts out paused at the beginning of `animation`, looping at normal speed.
+    pub fn new(scene: &scene::Scene, animation: usize) -> Self {
+        Self {
+            clip: Clip { animation, time: 0.0 },
+            blend: None,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+            rest_pose: scene.nodes.iter().map(NodePose::from_node).collect(),
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Switches to another animation, starting from its beginning.
+    pub fn set_animation(&mut self, animation: usize) {
+        self.clip = Clip { animation, time: 0.0 };
+    }
+
+    pub fn clip(&self) -> Clip {
+        self.clip
+    }
+
+    /// Jumps to `time` seconds into the current animation, a blended clip keeps its own time.
+    pub fn seek(&mut self, time: f32) {
+        self.clip.time = time;
+    }
+
+    /// Looping animations wrap around at the end, others stop on their last keyframe.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Playback rate, 1 is normal speed and negative values play backwards.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Blends `animation` on top of the current one by `weight`, 0 shows only the current animation and 1 only the blended one.
+    pub fn blend_with(&mut self, animation: usize, weight: f32) {
+        match &mut self.blend {
+            Some((clip, blend_weight)) if clip.animation == animation => *blend_weight = weight.clamp(0.0, 1.0),
+            _ => self.blend = Some((Clip { animation, time: 0.0 }, weight.clamp(0.0, 1.0))),
+        }
+    }
+
+    pub fn stop_blending(&mut self) {
+        self.blend = None;
+    }
+
+    /// Advances playback by `delta_time` seconds and writes the animated pose into the nodes of `scene`. Only the
+    /// properties the clips' channels animate are written, anything else stays as set from code.
+    pub fn update(&mut self, scene: &mut scene::Scene, delta_time: f32) {
+        if self.playing {
+            let step = delta_time * self.speed;
+            self.clip.time = self.advance(scene, self.clip, step);
+            if let Some((clip, weight)) = self.blend {
+                self.blend = Some((Clip { time: self.advance(scene, clip, step), ..clip }, weight));
+            }
+        }
+
+        let mut pose = self.rest_pose.clone();
+        let mut animated = Vec::new();
+        if let Some(animation) = scene.animations.get(self.clip.animation) {
+            animated = animation.apply(self.clip.time, &mut pose);
+        }
+        if let Some((clip, weight)) = self.blend {
+            if let Some(animation) = scene.animations.get(clip.animation) {
+                let mut blend_pose = self.rest_pose.clone();
+                animated.extend(animation.apply(clip.time, &mut blend_pose));
+                pose = pose.iter().zip(&blend_pose).map(|(a, b)| a.blend(b, weight)).collect();
+            }
+        }
+
+        for (node, property) in animated {
+            if let (Some(scene_node), Some(node_pose)) = (scene.nodes.get_mut(node), pose.get(node)) {
+                node_pose.write_property(scene_node, property);
+            }
+        }
+    }
+
+    fn advance(&self, scene: &scene::Scene, clip: Clip, step: f32) -> f32 {
+        let duration = scene.animations.get(clip.animation).map_or(0.0, |animation| animation.duration);
+        let time = clip.time + step;
+        if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "expected {:?}, got {:?}", expected, actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    fn sampler(input: Vec<f32>, output: Vec<f32>, interpolation: Interpolation, components: usize) -> Sampler {
+        Sampler { input, output, interpolation, components }
+    }
+
+    /// An animation driving the translation of node 0 with a single two key LINEAR track.
+    fn translation_animation(from: [f32; 3], to: [f32; 3], duration: f32) -> Animation {
+        Animation {
+            name: None,
+            channels: vec![Channel { node: 0, property: Property::Translation, sampler: 0 }],
+            samplers: vec![sampler(vec![0.0, duration], [from, to].concat(), Interpolation::Linear, 3)],
+            duration,
+        }
+    }
+
+    fn scene(animations: Vec<Animation>) -> scene::Scene {
+        let mesh = mesh::Mesh { verts: Vec::new(), indices: mesh::Indices::U16(Vec::new()), submeshes: Vec::new(), morph_targets: Vec::new() };
+        scene::Scene { animations, ..scene::Scene::from_mesh(mesh) }
+    }
+
+    #[test]
+    fn linear_midpoint() {
+        let track = sampler(vec![0.0, 2.0], vec![0.0, 0.0, 0.0, 2.0, 4.0, -6.0], Interpolation::Linear, 3);
+        assert_near(&track.sample(1.0, Property::Translation), &[1.0, 2.0, -3.0]);
+        // Outside the keyframes the ends are held
+        assert_near(&track.sample(-1.0, Property::Translation), &[0.0, 0.0, 0.0]);
+        assert_near(&track.sample(5.0, Property::Translation), &[2.0, 4.0, -6.0]);
+    }
+
+    #[test]
+    fn step_holds_until_next_key() {
+        let track = sampler(vec![0.0, 1.0, 2.0], vec![1.0, 5.0, 9.0], Interpolation::Step, 1);
+        assert_near(&track.sample(0.99, Property::Weights), &[1.0]);
+        assert_near(&track.sample(1.0, Property::Weights), &[5.0]);
+        assert_near(&track.sample(1.5, Property::Weights), &[5.0]);
+    }
+
+    #[test]
+    fn cubic_spline_scales_tangents_by_delta() {
+        // Keys at 1 and 3 so the delta is 2, stored as in-tangent, value, out-tangent
+        let track = sampler(vec![1.0, 3.0], vec![0.0, 0.0, 3.0, 1.0, 2.0, 0.0], Interpolation::CubicSpline, 1);
+        // At t = 0.5 the basis is 0.5, 0.125, 0.5 and -0.125: 0.5 * 0 + 2 * 0.125 * 3 + 0.5 * 2 - 2 * 0.125 * 1
+        assert_near(&track.sample(2.0, Property::Weights), &[1.5]);
+        // The ends are the values, not the tangents
+        assert_near(&track.sample(1.0, Property::Weights), &[0.0]);
+        assert_near(&track.sample(3.0, Property::Weights), &[2.0]);
+    }
+
+    #[test]
+    fn rotation_slerps_the_short_way() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        // The second key is a quarter turn around z stored negated, the long way round would be three quarters
+        let track = sampler(vec![0.0, 1.0], vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -half, -half], Interpolation::Linear, 4);
+        let value = track.sample(0.5, Property::Rotation);
+        let (sin, cos) = std::f32::consts::FRAC_PI_8.sin_cos();
+        // q and -q are the same rotation
+        let sign = value[3].signum();
+        assert_near(&value.iter().map(|component| component * sign).collect::<Vec<_>>(), &[0.0, 0.0, sin, cos]);
+    }
+
+    #[test]
+    fn rotation_is_normalized() {
+        let track = sampler(vec![0.0, 1.0], vec![0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 4.0], Interpolation::Step, 4);
+        assert_near(&track.sample(0.5, Property::Rotation), &[0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn advance_loops_or_clamps() {
+        let mut scene = scene(vec![translation_animation([0.0; 3], [2.0, 0.0, 0.0], 2.0)]);
+        let mut player = AnimationPlayer::new(&scene, 0);
+        player.play();
+        player.update(&mut scene, 2.5);
+        assert_near(&[player.clip().time], &[0.5]);
+        assert_near(&[scene.nodes[0].translation.x], &[0.5]);
+
+        // Backwards playback wraps around the start
+        player.set_speed(-1.0);
+        player.update(&mut scene, 1.0);
+        assert_near(&[player.clip().time], &[1.5]);
+
+        player.set_looping(false);
+        player.set_speed(1.0);
+        player.update(&mut scene, 5.0);
+        assert_near(&[player.clip().time], &[2.0]);
+        player.set_speed(-1.0);
+        player.update(&mut scene, 5.0);
+        assert_near(&[player.clip().time], &[0.0]);
+    }
+
+    #[test]
+    fn blend_with_mixes_clips() {
+        let mut scene = scene(vec![
+            translation_animation([2.0, 0.0, 0.0], [2.0, 0.0, 0.0], 1.0),
+            translation_animation([0.0, 4.0, 0.0], [0.0, 4.0, 0.0], 1.0),
+        ]);
+        let mut player = AnimationPlayer::new(&scene, 0);
+        player.blend_with(1, 0.25);
+        player.update(&mut scene, 0.0);
+        let translation = scene.nodes[0].translation;
+        assert_near(&[translation.x, translation.y, translation.z], &[1.5, 1.0, 0.0]);
+
+        // Weights are clamped to 0..1
+        player.blend_with(1, 2.0);
+        player.update(&mut scene, 0.0);
+        let translation = scene.nodes[0].translation;
+        assert_near(&[translation.x, translation.y, translation.z], &[0.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn unanimated_properties_keep_values_set_from_code() {
+        let mut scene = scene(vec![translation_animation([0.0; 3], [2.0, 0.0, 0.0], 2.0)]);
+        let mut player = AnimationPlayer::new(&scene, 0);
+        player.play();
+        scene.nodes[0].scale = cgmath::vec3(3.0, 3.0, 3.0);
+        scene.nodes[0].weights = vec![0.5];
+        player.update(&mut scene, 1.0);
+        assert_eq!(scene.nodes[0].scale, cgmath::vec3(3.0, 3.0, 3.0));
+        assert_eq!(scene.nodes[0].weights, vec![0.5]);
+        assert_near(&[scene.nodes[0].translation.x], &[1.0]);
+    }
+}
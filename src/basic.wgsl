@@ -1,14 +1,48 @@
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    position: vec4<f32>,
 };
 
 @group(1)@binding(0)
 var<uniform> camera: CameraUniform;
+// Copy of the opaque scene, sampled by transmissive materials
+@group(1)@binding(1)
+var t_scene_color: texture_2d<f32>;
+@group(1)@binding(2)
+var s_scene_color: sampler;
+
+// Laid out by light::LightUniform
+struct Light {
+    position: vec4<f32>,
+    // The direction the light travels in
+    direction: vec4<f32>,
+    // Color multiplied with intensity
+    color: vec4<f32>,
+    kind: u32,
+    // 0 for unlimited range
+    range: f32,
+    spot_scale: f32,
+    spot_offset: f32,
+};
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+// KHR_lights_punctual lights of the scene, never empty
+@group(1)@binding(3)
+var<storage, read> lights: array<Light>;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
+    @location(1) color: vec4<f32>,
     @location(2) uv: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+    // w is the bitangent handedness
+    @location(4) tangent: vec4<f32>,
+    @location(5) joints: vec4<u32>,
+    @location(6) weights: vec4<f32>,
+    @location(7) uv1: vec2<f32>,
 }
 
 struct InstanceInput {
@@ -20,8 +54,12 @@ struct InstanceInput {
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) vertex_color: vec3<f32>,
+    @location(0) vertex_color: vec4<f32>,
     @location(1) uv: vec2<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
+    @location(4) world_position: vec3<f32>,
+    @location(5) uv1: vec2<f32>,
 };
 
 @vertex
@@ -33,38 +71,425 @@ fn vs_main (
     out.clip_position = camera.view_proj * vec4<f32>(vert.position, 1.0);
     out.vertex_color = vert.color;
     out.uv = vert.uv;
+    out.uv1 = vert.uv1;
+    out.world_normal = vert.normal;
+    out.world_tangent = vert.tangent;
+    out.world_position = vert.position;
     return out;
 }
 
-@vertex
-fn vs_main_2 (
-    vert: VertexInput,
-    instance: InstanceInput
-) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
+fn instance_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+}
+
+fn transform_vertex(vert: VertexInput, model_matrix: mat4x4<f32>) -> VertexOutput {
+    // Only correct for uniform scale, non-uniform scale needs the inverse transpose
+    let normal_matrix = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    let world_position = model_matrix * vec4<f32>(vert.position, 1.0);
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(vert.position, 1.0);
-    out.vertex_color = vert.color.rgb;
+    out.clip_position = camera.view_proj * world_position;
+    out.vertex_color = vert.color;
     out.uv = vert.uv;
+    out.uv1 = vert.uv1;
+    out.world_normal = normalize(normal_matrix * vert.normal);
+    out.world_tangent = vec4<f32>(normalize(normal_matrix * vert.tangent.xyz), vert.tangent.w);
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+@vertex
+fn vs_main_2 (
+    vert: VertexInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    return transform_vertex(vert, instance_matrix(instance));
+}
+
+// Joint matrices of the skin being drawn, already multiplied with their inverse bind matrices
+@group(2)@binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+fn skin_matrix(vert: VertexInput) -> mat4x4<f32> {
+    // Vertices without weights stay where they are, same as skin::skin_vertices
+    if all(vert.weights == vec4<f32>(0.0)) {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joint_matrices[vert.joints.x] * vert.weights.x
+        + joint_matrices[vert.joints.y] * vert.weights.y
+        + joint_matrices[vert.joints.z] * vert.weights.z
+        + joint_matrices[vert.joints.w] * vert.weights.w;
+}
+
+@vertex
+fn vs_skinned (
+    vert: VertexInput,
+    instance: InstanceInput
+) -> VertexOutput {
+    return transform_vertex(vert, instance_matrix(instance) * skin_matrix(vert));
+}
+
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+    tangent: vec4<f32>,
+};
+
+// Laid out by mesh::GpuMesh, all deltas of target 0 followed by all deltas of target 1 and so on
+struct MorphTargets {
+    vertex_count: u32,
+    target_count: u32,
+    deltas: array<MorphDelta>,
+};
+
+@group(3)@binding(0)
+var<storage, read> morph_targets: MorphTargets;
+// Weights of the node being drawn
+@group(3)@binding(1)
+var<storage, read> morph_weights: array<f32>;
+
+fn morph_vertex(vert: VertexInput, vertex_index: u32) -> VertexInput {
+    var out = vert;
+    for (var i = 0u; i < morph_targets.target_count; i++) {
+        let weight = morph_weights[i];
+        let delta = morph_targets.deltas[i * morph_targets.vertex_count + vertex_index];
+        out.position += weight * delta.position.xyz;
+        out.normal += weight * delta.normal.xyz;
+        out.tangent = vec4<f32>(out.tangent.xyz + weight * delta.tangent.xyz, out.tangent.w);
+    }
     return out;
 }
 
+// vertex_index includes the submesh's base vertex, so it indexes the deltas of the whole mesh
+@vertex
+fn vs_morphed (
+    vert: VertexInput,
+    instance: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32
+) -> VertexOutput {
+    return transform_vertex(morph_vertex(vert, vertex_index), instance_matrix(instance));
+}
+
+@vertex
+fn vs_skinned_morphed (
+    vert: VertexInput,
+    instance: InstanceInput,
+    @builtin(vertex_index) vertex_index: u32
+) -> VertexOutput {
+    return transform_vertex(morph_vertex(vert, vertex_index), instance_matrix(instance) * skin_matrix(vert));
+}
+
+struct MaterialUniform {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    // Negative when alpha testing is disabled
+    alpha_cutoff: f32,
+    unlit: u32,
+    ior: f32,
+    specular_factor: f32,
+    specular_color_factor: vec4<f32>,
+    sheen_color_factor: vec4<f32>,
+    attenuation_color: vec4<f32>,
+    clearcoat_factor: f32,
+    clearcoat_roughness_factor: f32,
+    clearcoat_normal_scale: f32,
+    sheen_roughness_factor: f32,
+    transmission_factor: f32,
+    // In mesh space, 0 for thin walled materials
+    thickness_factor: f32,
+    // Infinite when the volume doesn't absorb light
+    attenuation_distance: f32,
+    // KHR_texture_transform, the first two rows of a 3x3 uv matrix per texture slot.
+    // w of the first row is the uv set the slot samples.
+    uv_transforms: array<vec4<f32>, 28>,
+};
+
+const SLOT_BASE_COLOR: u32 = 0u;
+const SLOT_METALLIC_ROUGHNESS: u32 = 1u;
+const SLOT_NORMAL: u32 = 2u;
+const SLOT_OCCLUSION: u32 = 3u;
+const SLOT_EMISSIVE: u32 = 4u;
+const SLOT_CLEARCOAT: u32 = 5u;
+const SLOT_CLEARCOAT_ROUGHNESS: u32 = 6u;
+const SLOT_CLEARCOAT_NORMAL: u32 = 7u;
+const SLOT_SHEEN_COLOR: u32 = 8u;
+const SLOT_SHEEN_ROUGHNESS: u32 = 9u;
+const SLOT_TRANSMISSION: u32 = 10u;
+const SLOT_THICKNESS: u32 = 11u;
+const SLOT_SPECULAR: u32 = 12u;
+const SLOT_SPECULAR_COLOR: u32 = 13u;
+
+fn slot_uv(in: VertexOutput, slot: u32) -> vec2<f32> {
+    let row_0 = material.uv_transforms[slot * 2u];
+    let uv = vec3<f32>(select(in.uv, in.uv1, row_0.w > 0.5), 1.0);
+    return vec2<f32>(dot(row_0.xyz, uv), dot(material.uv_transforms[slot * 2u + 1u].xyz, uv));
+}
+
 @group(0)@binding(0)
-var t_diffuse: texture_2d<f32>;
+var<uniform> material: MaterialUniform;
 @group(0)@binding(1)
-var s_diffuse: sampler;
+var t_base_color: texture_2d<f32>;
+@group(0)@binding(2)
+var s_base_color: sampler;
+@group(0)@binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0)@binding(4)
+var s_metallic_roughness: sampler;
+@group(0)@binding(5)
+var t_normal: texture_2d<f32>;
+@group(0)@binding(6)
+var s_normal: sampler;
+@group(0)@binding(7)
+var t_occlusion: texture_2d<f32>;
+@group(0)@binding(8)
+var s_occlusion: sampler;
+@group(0)@binding(9)
+var t_emissive: texture_2d<f32>;
+@group(0)@binding(10)
+var s_emissive: sampler;
+@group(0)@binding(11)
+var t_clearcoat: texture_2d<f32>;
+@group(0)@binding(12)
+var s_clearcoat: sampler;
+@group(0)@binding(13)
+var t_clearcoat_roughness: texture_2d<f32>;
+@group(0)@binding(14)
+var s_clearcoat_roughness: sampler;
+@group(0)@binding(15)
+var t_clearcoat_normal: texture_2d<f32>;
+@group(0)@binding(16)
+var s_clearcoat_normal: sampler;
+@group(0)@binding(17)
+var t_sheen_color: texture_2d<f32>;
+@group(0)@binding(18)
+var s_sheen_color: sampler;
+@group(0)@binding(19)
+var t_sheen_roughness: texture_2d<f32>;
+@group(0)@binding(20)
+var s_sheen_roughness: sampler;
+@group(0)@binding(21)
+var t_transmission: texture_2d<f32>;
+@group(0)@binding(22)
+var s_transmission: sampler;
+@group(0)@binding(23)
+var t_thickness: texture_2d<f32>;
+@group(0)@binding(24)
+var s_thickness: sampler;
+@group(0)@binding(25)
+var t_specular: texture_2d<f32>;
+@group(0)@binding(26)
+var s_specular: sampler;
+@group(0)@binding(27)
+var t_specular_color: texture_2d<f32>;
+@group(0)@binding(28)
+var s_specular_color: sampler;
+
+const PI: f32 = 3.14159265;
+// Flat ambient term on top of the scene's lights
+const AMBIENT_COLOR: vec3<f32> = vec3<f32>(0.3, 0.3, 0.3);
+
+// Direction towards a light and the radiance it delivers at the shaded point
+struct LightSample {
+    l: vec3<f32>,
+    radiance: vec3<f32>,
+};
+
+fn sample_light(light: Light, position: vec3<f32>) -> LightSample {
+    if light.kind == LIGHT_DIRECTIONAL {
+        return LightSample(-light.direction.xyz, light.color.rgb);
+    }
+    let to_light = light.position.xyz - position;
+    let distance2 = max(dot(to_light, to_light), 0.0001);
+    let l = to_light * inverseSqrt(distance2);
+    // Inverse square falloff, windowed to reach zero at the range as KHR_lights_punctual recommends
+    var attenuation = 1.0 / distance2;
+    if light.range > 0.0 {
+        let ratio2 = distance2 / (light.range * light.range);
+        attenuation *= clamp(1.0 - ratio2 * ratio2, 0.0, 1.0);
+    }
+    if light.kind == LIGHT_SPOT {
+        let spot = clamp(dot(light.direction.xyz, -l) * light.spot_scale + light.spot_offset, 0.0, 1.0);
+        attenuation *= spot * spot;
+    }
+    return LightSample(l, light.color.rgb * attenuation);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+fn visibility_smith_ggx(n_dot_l: f32, n_dot_v: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+    let ggx = ggx_v + ggx_l;
+    if ggx > 0.0 {
+        return 0.5 / ggx;
+    }
+    return 0.0;
+}
+
+fn fresnel_schlick(f0: vec3<f32>, f90: vec3<f32>, v_dot_h: f32) -> vec3<f32> {
+    return f0 + (f90 - f0) * pow(clamp(1.0 - v_dot_h, 0.0, 1.0), 5.0);
+}
+
+// KHR_materials_sheen, the "Charlie" distribution and Neubelt's visibility term
+fn distribution_charlie(n_dot_h: f32, alpha: f32) -> f32 {
+    let inv_alpha = 1.0 / alpha;
+    let sin2 = 1.0 - n_dot_h * n_dot_h;
+    return (2.0 + inv_alpha) * pow(sin2, inv_alpha * 0.5) / (2.0 * PI);
+}
+
+fn visibility_neubelt(n_dot_l: f32, n_dot_v: f32) -> f32 {
+    return 1.0 / (4.0 * (n_dot_l + n_dot_v - n_dot_l * n_dot_v));
+}
+
+// KHR_materials_transmission and KHR_materials_volume, the opaque scene seen through the surface
+fn transmitted_light(in: VertexOutput, n: vec3<f32>, v: vec3<f32>, thickness: f32) -> vec3<f32> {
+    // Follow the refracted view ray through the volume to where it leaves the object, thin walls don't bend it.
+    // The thickness is in mesh space, the model scale is ignored
+    let refracted = refract(-v, n, 1.0 / material.ior);
+    let exit = camera.view_proj * vec4<f32>(in.world_position + refracted * thickness, 1.0);
+    let ndc = exit.xy / exit.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    // Explicit level, this runs after the alpha test
+    let background = textureSampleLevel(t_scene_color, s_scene_color, uv, 0.0).rgb;
+    // Beer-Lambert absorption along the path
+    return background * pow(material.attenuation_color.rgb, vec3<f32>(thickness / material.attenuation_distance));
+}
+
+fn shading_normal(in: VertexOutput, front_facing: bool, tangent_normal_sample: vec3<f32>, scale: f32) -> vec3<f32> {
+    var normal = normalize(in.world_normal);
+    var tangent = normalize(in.world_tangent.xyz);
+    // Double sided materials light the back face with the flipped normal
+    if !front_facing {
+        normal = -normal;
+        tangent = -tangent;
+    }
+    let bitangent = cross(normal, tangent) * in.world_tangent.w;
+    var tangent_normal = tangent_normal_sample * 2.0 - 1.0;
+    tangent_normal = vec3<f32>(tangent_normal.xy * scale, tangent_normal.z);
+    return normalize(mat3x3<f32>(tangent, bitangent, normal) * tangent_normal);
+}
+
+fn shade(in: VertexOutput, front_facing: bool) -> vec4<f32> {
+    // Sample everything before the alpha test, implicit derivatives need uniform control flow
+    let base_color = material.base_color_factor * in.vertex_color * textureSample(t_base_color, s_base_color, slot_uv(in, SLOT_BASE_COLOR));
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, slot_uv(in, SLOT_METALLIC_ROUGHNESS));
+    let n = shading_normal(in, front_facing, textureSample(t_normal, s_normal, slot_uv(in, SLOT_NORMAL)).xyz, material.normal_scale);
+    let occlusion_sample = textureSample(t_occlusion, s_occlusion, slot_uv(in, SLOT_OCCLUSION)).r;
+    let emissive_sample = textureSample(t_emissive, s_emissive, slot_uv(in, SLOT_EMISSIVE)).rgb;
+    let clearcoat_sample = textureSample(t_clearcoat, s_clearcoat, slot_uv(in, SLOT_CLEARCOAT)).r;
+    let clearcoat_roughness_sample = textureSample(t_clearcoat_roughness, s_clearcoat_roughness, slot_uv(in, SLOT_CLEARCOAT_ROUGHNESS)).g;
+    let clearcoat_n = shading_normal(in, front_facing, textureSample(t_clearcoat_normal, s_clearcoat_normal, slot_uv(in, SLOT_CLEARCOAT_NORMAL)).xyz, material.clearcoat_normal_scale);
+    let sheen_color_sample = textureSample(t_sheen_color, s_sheen_color, slot_uv(in, SLOT_SHEEN_COLOR)).rgb;
+    let sheen_roughness_sample = textureSample(t_sheen_roughness, s_sheen_roughness, slot_uv(in, SLOT_SHEEN_ROUGHNESS)).a;
+    let transmission_sample = textureSample(t_transmission, s_transmission, slot_uv(in, SLOT_TRANSMISSION)).r;
+    let thickness_sample = textureSample(t_thickness, s_thickness, slot_uv(in, SLOT_THICKNESS)).g;
+    let specular_sample = textureSample(t_specular, s_specular, slot_uv(in, SLOT_SPECULAR)).a;
+    let specular_color_sample = textureSample(t_specular_color, s_specular_color, slot_uv(in, SLOT_SPECULAR_COLOR)).rgb;
+    if base_color.a < material.alpha_cutoff {
+        discard;
+    }
+    // KHR_materials_unlit
+    if material.unlit != 0u {
+        return base_color;
+    }
+
+    let metallic = clamp(material.metallic_factor * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material.roughness_factor * metallic_roughness.g, 0.04, 1.0);
+    let alpha = roughness * roughness;
+
+    let v = normalize(camera.position.xyz - in.world_position);
+    let n_dot_v = clamp(abs(dot(n, v)), 0.001, 1.0);
+
+    // KHR_materials_ior and KHR_materials_specular tint and scale the dielectric reflection
+    let specular_weight = material.specular_factor * specular_sample;
+    let ior_f0 = pow((material.ior - 1.0) / (material.ior + 1.0), 2.0);
+    let dielectric_f0 = min(ior_f0 * material.specular_color_factor.rgb * specular_color_sample, vec3<f32>(1.0)) * specular_weight;
+    let f0 = mix(dielectric_f0, base_color.rgb, metallic);
+    let f90 = mix(vec3<f32>(specular_weight), vec3<f32>(1.0), metallic);
+
+    let occlusion = 1.0 + material.occlusion_strength * (occlusion_sample - 1.0);
+    let ambient = AMBIENT_COLOR * base_color.rgb * occlusion;
+    let emissive = material.emissive_factor.rgb * emissive_sample;
+
+    let sheen_color = material.sheen_color_factor.rgb * sheen_color_sample;
+    let sheen_alpha = pow(max(material.sheen_roughness_factor * sheen_roughness_sample, 0.07), 2.0);
+
+    let clearcoat = clamp(material.clearcoat_factor * clearcoat_sample, 0.0, 1.0);
+    let clearcoat_roughness = clamp(material.clearcoat_roughness_factor * clearcoat_roughness_sample, 0.04, 1.0);
+    let clearcoat_alpha = clearcoat_roughness * clearcoat_roughness;
+    let clearcoat_n_dot_v = clamp(abs(dot(clearcoat_n, v)), 0.001, 1.0);
+    let clearcoat_fresnel = fresnel_schlick(vec3<f32>(0.04), vec3<f32>(1.0), clearcoat_n_dot_v).x;
+
+    var diffuse_light = ambient;
+    var specular_light = vec3<f32>(0.0);
+    var sheen_light = vec3<f32>(0.0);
+    var clearcoat_light = vec3<f32>(0.0);
+    for (var i = 0u; i < arrayLength(&lights); i++) {
+        let light = sample_light(lights[i], in.world_position);
+        let l = light.l;
+        let h = normalize(l + v);
+        let n_dot_l = clamp(dot(n, l), 0.0, 1.0);
+        let n_dot_h = clamp(dot(n, h), 0.0, 1.0);
+        let v_dot_h = clamp(dot(v, h), 0.0, 1.0);
+        let radiance = light.radiance * n_dot_l;
+
+        let fresnel = fresnel_schlick(f0, f90, v_dot_h);
+        diffuse_light += (vec3<f32>(1.0) - fresnel) * (1.0 - metallic) * base_color.rgb / PI * radiance;
+        specular_light += fresnel * distribution_ggx(n_dot_h, alpha) * visibility_smith_ggx(n_dot_l, n_dot_v, alpha) * radiance;
+        sheen_light += sheen_color * distribution_charlie(n_dot_h, sheen_alpha) * visibility_neubelt(n_dot_l, n_dot_v) * radiance;
+
+        // Clearcoat is a second, colorless GGX layer with its own normal and roughness
+        let clearcoat_n_dot_l = clamp(dot(clearcoat_n, l), 0.0, 1.0);
+        let clearcoat_n_dot_h = clamp(dot(clearcoat_n, h), 0.0, 1.0);
+        clearcoat_light += clearcoat_fresnel * distribution_ggx(clearcoat_n_dot_h, clearcoat_alpha)
+            * visibility_smith_ggx(clearcoat_n_dot_l, clearcoat_n_dot_v, clearcoat_alpha) * light.radiance * clearcoat_n_dot_l;
+    }
+
+    // Transmission replaces the diffuse lobe with the scene behind the surface, tinted by the base color
+    let transmission = clamp(material.transmission_factor * transmission_sample, 0.0, 1.0);
+    if transmission > 0.0 {
+        let thickness = material.thickness_factor * thickness_sample;
+        let fresnel = fresnel_schlick(f0, f90, n_dot_v);
+        let transmitted = (vec3<f32>(1.0) - fresnel) * (1.0 - metallic) * base_color.rgb * transmitted_light(in, n, v, thickness);
+        diffuse_light = mix(diffuse_light, transmitted, transmission);
+    }
+
+    // Sheen sits on top of the base layer and takes away the energy it reflects
+    let sheen_scaling = 1.0 - max(sheen_color.r, max(sheen_color.g, sheen_color.b)) * 0.157;
+    var color = (diffuse_light + specular_light) * sheen_scaling + sheen_light + emissive;
+    color = color * (1.0 - clearcoat * clearcoat_fresnel) + clearcoat * clearcoat_light;
+    return vec4(color, base_color.a);
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4(in.vertex_color * textureSample(t_diffuse, s_diffuse, in.uv).rgb, 1.0);
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    return shade(in, front_facing);
 }
 
 @fragment
-fn fs_main_2(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4(in.vertex_color  * textureSample(t_diffuse, s_diffuse, in.uv).rgb, 1.0);
+fn fs_main_2(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    // Unlit preview of the base color
+    let base_color = material.base_color_factor * in.vertex_color * textureSample(t_base_color, s_base_color, slot_uv(in, SLOT_BASE_COLOR));
+    if base_color.a < material.alpha_cutoff {
+        discard;
+    }
+    return base_color;
 }
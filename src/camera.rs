@@ -1,19 +1,31 @@
 use std::ops::Add;
 
-use cgmath;
+use cgmath::InnerSpace;
 
+use crate::mesh::{self, GltfError};
+
+
+// Maps OpenGL's -1..1 clip space depth to wgpu's 0..1, the arguments are columns
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    /// World space camera position, w unused
+    position: [f32; 4],
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl  CameraUniform {
@@ -21,14 +33,25 @@ impl  CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            position: [0.0, 0.0, 0.0, 1.0],
         }
     }
 
     pub fn update_view_projection(&mut self, camera: &Camera) {
         self.view_proj = camera.generate_view_projection_matrix().into();
+        self.position = camera.position.to_homogeneous().into();
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Uses `Camera::fov_vertical` and `Camera::aspect_ratio`
+    Perspective,
+    /// Half the width and height of the view volume
+    Orthographic { xmag: f32, ymag: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Camera {
     pub position: cgmath::Point3<f32>,
     pub forward: cgmath::Vector3<f32>,
@@ -36,18 +59,129 @@ pub struct Camera {
     pub aspect_ratio: f32,
     pub fov_vertical: f32,
     pub znear: f32,
+    /// Infinite for a perspective projection without a far plane
     pub zfar: f32,
+    pub projection: Projection,
 }
 
 impl Camera {
     pub fn generate_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         // TODO: Cache matrix calculation
         let view = cgmath::Matrix4::look_at_rh(self.position, self.position + self.forward, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fov_vertical), self.aspect_ratio, self.znear, self.zfar);
+        let proj = match self.projection {
+            Projection::Perspective if self.zfar.is_infinite() => {
+                // The limit of the perspective matrix as zfar goes to infinity, as given by the glTF spec
+                let f = 1.0 / (cgmath::Rad::from(cgmath::Deg(self.fov_vertical)).0 / 2.0).tan();
+                #[rustfmt::skip]
+                let proj = cgmath::Matrix4::new(
+                    f / self.aspect_ratio, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, -1.0, -1.0,
+                    0.0, 0.0, -2.0 * self.znear, 0.0,
+                );
+                proj
+            }
+            Projection::Perspective => cgmath::perspective(cgmath::Deg(self.fov_vertical), self.aspect_ratio, self.znear, self.zfar),
+            Projection::Orthographic { xmag, ymag } => cgmath::ortho(-xmag, xmag, -ymag, ymag, self.znear, self.zfar),
+        };
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
 }
 
+/// A glTF camera, it only becomes a `Camera` once a node places it in the scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfCamera {
+    pub name: Option<String>,
+    pub projection: Projection,
+    /// None leaves the aspect ratio up to the viewport
+    pub aspect_ratio: Option<f32>,
+    /// In radians, unused by orthographic cameras
+    pub yfov: f32,
+    pub znear: f32,
+    /// Infinite when a perspective camera has no far plane
+    pub zfar: f32,
+}
+
+impl GltfCamera {
+    pub(crate) fn from_gltf(gltf: &mesh::GltfDocument, index: usize) -> Result<Self, GltfError> {
+        let camera = &gltf.json["cameras"][index];
+        let object = format!("cameras[{}]", index);
+        let required_f32 = |value: &json::JsonValue, object: &str, property: &'static str| {
+            value[property].as_f32().ok_or_else(|| GltfError::MissingProperty { object: object.to_string(), property })
+        };
+
+        match mesh::required_str(camera, &object, "type")? {
+            "perspective" => {
+                let perspective = &camera["perspective"];
+                let object = format!("{}.perspective", object);
+                Ok(Self {
+                    name: camera["name"].as_str().map(String::from),
+                    projection: Projection::Perspective,
+                    aspect_ratio: perspective["aspectRatio"].as_f32(),
+                    yfov: required_f32(perspective, &object, "yfov")?,
+                    znear: required_f32(perspective, &object, "znear")?,
+                    zfar: perspective["zfar"].as_f32().unwrap_or(f32::INFINITY),
+                })
+            }
+            "orthographic" => {
+                let orthographic = &camera["orthographic"];
+                let object = format!("{}.orthographic", object);
+                Ok(Self {
+                    name: camera["name"].as_str().map(String::from),
+                    projection: Projection::Orthographic {
+                        xmag: required_f32(orthographic, &object, "xmag")?,
+                        ymag: required_f32(orthographic, &object, "ymag")?,
+                    },
+                    aspect_ratio: None,
+                    yfov: 0.0,
+                    znear: required_f32(orthographic, &object, "znear")?,
+                    zfar: required_f32(orthographic, &object, "zfar")?,
+                })
+            }
+            _ => Err(GltfError::MissingProperty { object, property: "type" }),
+        }
+    }
+
+    /// The camera object `from_gltf` reads.
+    pub(crate) fn to_gltf(&self) -> json::JsonValue {
+        let mut camera = match self.projection {
+            Projection::Perspective => {
+                let mut perspective = json::object! { "yfov": self.yfov, "znear": self.znear };
+                if let Some(aspect_ratio) = self.aspect_ratio {
+                    perspective["aspectRatio"] = aspect_ratio.into();
+                }
+                // Leaving zfar out makes the projection infinite
+                if self.zfar.is_finite() {
+                    perspective["zfar"] = self.zfar.into();
+                }
+                json::object! { "type": "perspective", "perspective": perspective }
+            }
+            Projection::Orthographic { xmag, ymag } => json::object! {
+                "type": "orthographic",
+                "orthographic": { "xmag": xmag, "ymag": ymag, "znear": self.znear, "zfar": self.zfar },
+            },
+        };
+        if let Some(name) = &self.name {
+            camera["name"] = name.as_str().into();
+        }
+        camera
+    }
+
+    /// The camera as seen from a node with the given world transform, glTF cameras look down their node's -Z with +Y up.
+    pub fn to_camera(&self, world_transform: cgmath::Matrix4<f32>, viewport_aspect_ratio: f32) -> Camera {
+        Camera {
+            position: cgmath::Point3::from_homogeneous(world_transform * cgmath::vec4(0.0, 0.0, 0.0, 1.0)),
+            forward: (world_transform * cgmath::Vector4::unit_z() * -1.0).truncate().normalize(),
+            up: (world_transform * cgmath::Vector4::unit_y()).truncate().normalize(),
+            aspect_ratio: self.aspect_ratio.unwrap_or(viewport_aspect_ratio),
+            fov_vertical: cgmath::Deg::from(cgmath::Rad(self.yfov)).0,
+            znear: self.znear,
+            zfar: self.zfar,
+            projection: self.projection,
+        }
+    }
+}
+
 pub struct FpsCamera {
     camera: Camera,
     
@@ -0,0 +1,588 @@
+use json::JsonValue;
+
+use crate::mesh::{self, COMPONENT_TYPE_FLOAT, COMPONENT_TYPE_UNSIGNED_INT, COMPONENT_TYPE_UNSIGNED_SHORT};
+use crate::scene::Scene;
+use crate::stl;
+
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Why writing a glTF file failed.
+#[derive(Debug)]
+pub enum ExportError {
+    Io { path: std::path::PathBuf, source: std::io::Error },
+    /// An image could not be encoded as png
+    Image { index: usize, reason: String },
+    /// glTF requires every mesh to have at least one primitive
+    EmptyMesh(usize),
+    /// STL only stores triangles, point submeshes can't be written
+    NoTriangles,
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io { path, source } => write!(f, "failed to write {}: {}", path.display(), source),
+            ExportError::Image { index, reason } => write!(f, "images[{}]: {}", index, reason),
+            ExportError::EmptyMesh(index) => write!(f, "meshes[{}] has no triangles or points", index),
+            ExportError::NoTriangles => write!(f, "mesh has no triangles"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `scene` as a .gltf file with its binary data in a .bin file next to it, scenes without binary data get no .bin.
+/// Animations and EXT_mesh_gpu_instancing instances aren't written.
+pub fn save_gltf(scene: &Scene, path: &std::path::Path) -> Result<(), ExportError> {
+    let (mut gltf, bin) = serialize(scene)?;
+    if bin.is_empty() {
+        return write_file(path, gltf.pretty(2).as_bytes());
+    }
+    let bin_path = path.with_extension("bin");
+    // The uri is relative to the .gltf file
+    let bin_uri = bin_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    gltf["buffers"][0]["uri"] = bin_uri.into();
+    write_file(path, gltf.pretty(2).as_bytes())?;
+    write_file(&bin_path, &bin)
+}
+
+/// Writes `scene` as a single binary .glb file.
+pub fn save_glb(scene: &Scene, path: &std::path::Path) -> Result<(), ExportError> {
+    let (gltf, mut bin) = serialize(scene)?;
+    // Both chunks have to be 4 byte aligned, json is padded with spaces and binary data with zeros
+    let mut json = gltf.dump().into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let chunk = |chunk_type: u32, data: &[u8]| {
+        let mut chunk = Vec::with_capacity(mesh::GLB_CHUNK_HEADER_LENGTH + data.len());
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(chunk_type.to_le_bytes());
+        chunk.extend(data);
+        chunk
+    };
+    let json_chunk = chunk(mesh::GLB_CHUNK_TYPE_JSON, &json);
+    // Without a buffer there is nothing for a BIN chunk to hold
+    let bin_chunk = if bin.is_empty() { Vec::new() } else { chunk(mesh::GLB_CHUNK_TYPE_BIN, &bin) };
+
+    let length = mesh::GLB_HEADER_LENGTH + json_chunk.len() + bin_chunk.len();
+    let mut glb = Vec::with_capacity(length);
+    glb.extend(mesh::GLB_MAGIC.to_le_bytes());
+    glb.extend(2u32.to_le_bytes());
+    glb.extend((length as u32).to_le_bytes());
+    glb.extend(json_chunk);
+    glb.extend(bin_chunk);
+    write_file(path, &glb)
+}
+
+/// Writes the triangles of every triangle submesh of `mesh` as a binary STL file. STL only stores positions,
+/// each facet's normal is recomputed from its winding. Meshes without triangles are an error rather than an empty file.
+pub fn save_stl(mesh: &mesh::Mesh, path: &std::path::Path) -> Result<(), ExportError> {
+    use cgmath::InnerSpace;
+    let triangle_submeshes = || mesh.submeshes.iter().filter(|submesh| submesh.topology == mesh::Topology::Triangles);
+    let triangle_count = triangle_submeshes().map(|submesh| submesh.index_range.len() / 3).sum::<usize>();
+    if triangle_count == 0 {
+        return Err(ExportError::NoTriangles);
+    }
+    let mut stl = Vec::with_capacity(stl::BINARY_HEADER_LENGTH + 4 + triangle_count * stl::BINARY_TRIANGLE_LENGTH);
+    // Readers take a header starting with "solid" for an ASCII file, so don't
+    let mut header = b"binary STL written by webgpu_renderer".to_vec();
+    header.resize(stl::BINARY_HEADER_LENGTH, 0);
+    stl.extend(header);
+    stl.extend((triangle_count as u32).to_le_bytes());
+    for submesh in triangle_submeshes() {
+        let indices = submesh.index_range.clone().map(|i| (mesh.indices.get(i as usize) as i64 + submesh.base_vertex as i64) as usize).collect::<Vec<_>>();
+        for triangle in indices.chunks_exact(3) {
+            let corners = [0, 1, 2].map(|corner| mesh.verts[triangle[corner]].position);
+            let [p0, p1, p2] = corners.map(cgmath::Vector3::from);
+            let normal = (p1 - p0).cross(p2 - p0);
+            // Degenerate facets get a zero normal, which readers take as "compute it yourself"
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { normal };
+            for value in AsRef::<[f32; 3]>::as_ref(&normal).iter().chain(corners.iter().flatten()) {
+                stl.extend(value.to_le_bytes());
+            }
+            // Attribute byte count, unused
+            stl.extend(0u16.to_le_bytes());
+        }
+    }
+    write_file(path, &stl)
+}
+
+fn write_file(path: &std::path::Path, data: &[u8]) -> Result<(), ExportError> {
+    std::fs::write(path, data).map_err(|source| ExportError::Io { path: path.to_path_buf(), source })
+}
+
+/// Accumulates the single binary buffer of the written file with its bufferViews and accessors.
+#[derive(Default)]
+struct BufferWriter {
+    data: Vec<u8>,
+    buffer_views: Vec<JsonValue>,
+    accessors: Vec<JsonValue>,
+}
+
+impl BufferWriter {
+    fn buffer_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // Keeps every view aligned for the widest component type
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        let mut buffer_view = json::object! {
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        };
+        if let Some(target) = target {
+            buffer_view["target"] = target.into();
+        }
+        self.data.extend_from_slice(bytes);
+        self.buffer_views.push(buffer_view);
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(&mut self, buffer_view: usize, component_type: u32, count: usize, accessor_type: &str) -> usize {
+        self.accessors.push(json::object! {
+            "bufferView": buffer_view,
+            "componentType": component_type,
+            "count": count,
+            "type": accessor_type,
+        });
+        self.accessors.len() - 1
+    }
+
+    /// Writes float vectors, `with_bounds` adds the min and max that POSITION accessors require.
+    fn f32_accessor<const N: usize>(&mut self, values: &[[f32; N]], accessor_type: &str, target: Option<u32>, with_bounds: bool) -> usize {
+        let floats = values.iter().flatten().copied().collect::<Vec<f32>>();
+        let buffer_view = self.buffer_view(bytemuck::cast_slice(&floats), target);
+        let accessor = self.accessor(buffer_view, COMPONENT_TYPE_FLOAT, values.len(), accessor_type);
+        if with_bounds {
+            let mut min = [f32::INFINITY; N];
+            let mut max = [f32::NEG_INFINITY; N];
+            for value in values {
+                for c in 0..N {
+                    min[c] = min[c].min(value[c]);
+                    max[c] = max[c].max(value[c]);
+                }
+            }
+            self.accessors[accessor]["min"] = min.to_vec().into();
+            self.accessors[accessor]["max"] = max.to_vec().into();
+        }
+        accessor
+    }
+
+    /// Writes indices as u16 when they fit, 65535 is left out since it restarts strips on some APIs.
+    fn index_accessor(&mut self, indices: &[u32]) -> usize {
+        if indices.iter().all(|index| *index < u16::MAX as u32) {
+            let indices = indices.iter().map(|index| *index as u16).collect::<Vec<_>>();
+            let buffer_view = self.buffer_view(bytemuck::cast_slice(&indices), Some(TARGET_ELEMENT_ARRAY_BUFFER));
+            self.accessor(buffer_view, COMPONENT_TYPE_UNSIGNED_SHORT, indices.len(), "SCALAR")
+        } else {
+            let buffer_view = self.buffer_view(bytemuck::cast_slice(indices), Some(TARGET_ELEMENT_ARRAY_BUFFER));
+            self.accessor(buffer_view, COMPONENT_TYPE_UNSIGNED_INT, indices.len(), "SCALAR")
+        }
+    }
+}
+
+/// Builds the glTF json and its binary buffer. The buffer is left without a uri, which is what a .glb needs.
+fn serialize(scene: &Scene) -> Result<(JsonValue, Vec<u8>), ExportError> {
+    let mut buffer = BufferWriter::default();
+    let mut extensions_used = Vec::<String>::new();
+    let mut gltf = json::object! {
+        "asset": { "version": "2.0", "generator": "webgpu_renderer" },
+    };
+
+    let mut meshes = Vec::new();
+    for (index, mesh) in scene.meshes.iter().enumerate() {
+        meshes.push(serialize_mesh(&mut buffer, mesh, index)?);
+        if mesh.submeshes.iter().any(|submesh| !submesh.variant_materials.is_empty()) {
+            extensions_used.push("KHR_materials_variants".to_string());
+        }
+    }
+
+    let materials = scene.materials.iter().map(|material| material.to_gltf(&mut extensions_used)).collect::<Vec<_>>();
+
+    // Images are stored as png in the binary buffer, whatever format they were loaded from
+    let mut images = Vec::new();
+    for (index, image) in scene.images.iter().enumerate() {
+        let mut png = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .map_err(|error| ExportError::Image { index, reason: error.to_string() })?;
+        let buffer_view = buffer.buffer_view(&png, None);
+        images.push(json::object! { "bufferView": buffer_view, "mimeType": "image/png" });
+    }
+    // One sampler per texture keeps the indices simple, samplers are tiny
+    let samplers = scene.textures.iter().map(|texture| texture.sampler.to_gltf()).collect::<Vec<_>>();
+    let textures = scene.textures.iter().enumerate().map(|(index, texture)| json::object! { "source": texture.image, "sampler": index }).collect::<Vec<_>>();
+
+    let mut skins = Vec::new();
+    for skin in &scene.skins {
+        let matrices = skin.inverse_bind_matrices.iter().map(|matrix| -> [f32; 16] { *AsRef::<[f32; 16]>::as_ref(matrix) }).collect::<Vec<_>>();
+        let inverse_bind_matrices = buffer.f32_accessor(&matrices, "MAT4", None, false);
+        let mut object = json::object! { "joints": skin.joints.clone(), "inverseBindMatrices": inverse_bind_matrices };
+        if let Some(name) = &skin.name {
+            object["name"] = name.as_str().into();
+        }
+        skins.push(object);
+    }
+
+    let cameras = scene.cameras.iter().map(|camera| camera.to_gltf()).collect::<Vec<_>>();
+    let nodes = scene.nodes.iter().map(|node| {
+        let mut object = JsonValue::new_object();
+        if let Some(name) = &node.name {
+            object["name"] = name.as_str().into();
+        }
+        if !node.children.is_empty() {
+            object["children"] = node.children.clone().into();
+        }
+        if let Some(mesh) = node.mesh {
+            object["mesh"] = mesh.into();
+        }
+        if let Some(skin) = node.skin {
+            object["skin"] = skin.into();
+        }
+        if let Some(camera) = node.camera {
+            object["camera"] = camera.into();
+        }
+        if let Some(light) = node.light {
+            object["extensions"]["KHR_lights_punctual"] = json::object! { "light": light };
+        }
+        match node.matrix {
+            Some(matrix) => object["matrix"] = AsRef::<[f32; 16]>::as_ref(&matrix).to_vec().into(),
+            None => {
+                object["translation"] = AsRef::<[f32; 3]>::as_ref(&node.translation).to_vec().into();
+                // glTF stores quaternions as x, y, z, w
+                let rotation = node.rotation;
+                object["rotation"] = vec![rotation.v.x, rotation.v.y, rotation.v.z, rotation.s].into();
+                object["scale"] = AsRef::<[f32; 3]>::as_ref(&node.scale).to_vec().into();
+            }
+        }
+        if !node.weights.is_empty() {
+            object["weights"] = node.weights.clone().into();
+        }
+        object
+    }).collect::<Vec<_>>();
+    if !scene.lights.is_empty() {
+        gltf["extensions"]["KHR_lights_punctual"] = json::object! { "lights": scene.lights.iter().map(|light| light.to_gltf()).collect::<Vec<_>>() };
+        extensions_used.push("KHR_lights_punctual".to_string());
+    }
+    if !scene.variants.is_empty() {
+        let variants = scene.variants.iter().map(|name| json::object! { "name": name.as_str() }).collect::<Vec<_>>();
+        gltf["extensions"]["KHR_materials_variants"] = json::object! { "variants": variants };
+        extensions_used.push("KHR_materials_variants".to_string());
+    }
+
+    // Empty arrays aren't allowed, leave out what the scene doesn't have
+    for (property, values) in [
+        ("meshes", meshes), ("materials", materials), ("images", images), ("samplers", samplers), ("textures", textures),
+        ("skins", skins), ("cameras", cameras), ("nodes", nodes),
+        ("bufferViews", std::mem::take(&mut buffer.buffer_views)), ("accessors", std::mem::take(&mut buffer.accessors)),
+    ] {
+        if !values.is_empty() {
+            gltf[property] = values.into();
+        }
+    }
+    if !scene.nodes.is_empty() {
+        gltf["scenes"] = json::array![{ "nodes": scene.roots.clone() }];
+        gltf["scene"] = 0.into();
+    }
+    extensions_used.sort();
+    extensions_used.dedup();
+    if !extensions_used.is_empty() {
+        gltf["extensionsUsed"] = extensions_used.into();
+    }
+    if !buffer.data.is_empty() {
+        gltf["buffers"] = json::array![{ "byteLength": buffer.data.len() }];
+    }
+    Ok((gltf, buffer.data))
+}
+
+/// Each submesh becomes a primitive with the vertices its indices reach, so a loaded mesh is written back the way it was read.
+fn serialize_mesh(buffer: &mut BufferWriter, mesh: &mesh::Mesh, index: usize) -> Result<JsonValue, ExportError> {
+    let mut primitives = Vec::new();
+    for submesh in &mesh.submeshes {
+        let indices = submesh.index_range.clone().map(|i| (mesh.indices.get(i as usize) as i64 + submesh.base_vertex as i64) as u32).collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (indices.iter().min().copied(), indices.iter().max().copied()) else { continue };
+        let vertex_range = first as usize..last as usize + 1;
+        let verts = &mesh.verts[vertex_range.clone()];
+        let local_indices = indices.iter().map(|index| index - first).collect::<Vec<_>>();
+
+        let mut attributes = json::object! {
+            "POSITION": buffer.f32_accessor(&verts.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), "VEC3", Some(TARGET_ARRAY_BUFFER), true),
+            "NORMAL": buffer.f32_accessor(&verts.iter().map(|vertex| vertex.normal).collect::<Vec<_>>(), "VEC3", Some(TARGET_ARRAY_BUFFER), false),
+            "TANGENT": buffer.f32_accessor(&verts.iter().map(|vertex| vertex.tangent).collect::<Vec<_>>(), "VEC4", Some(TARGET_ARRAY_BUFFER), false),
+            "TEXCOORD_0": buffer.f32_accessor(&verts.iter().map(|vertex| vertex.uv).collect::<Vec<_>>(), "VEC2", Some(TARGET_ARRAY_BUFFER), false),
+        };
+        // The loader fills in white and zero weights for primitives without these, only write them when they carry something
+        if verts.iter().any(|vertex| vertex.uv1 != mesh::Vertex::default().uv1) {
+            attributes["TEXCOORD_1"] = buffer.f32_accessor(&verts.iter().map(|vertex| vertex.uv1).collect::<Vec<_>>(), "VEC2", Some(TARGET_ARRAY_BUFFER), false).into();
+        }
+        if verts.iter().any(|vertex| vertex.color != mesh::Vertex::default().color) {
+            attributes["COLOR_0"] = buffer.f32_accessor(&verts.iter().map(|vertex| vertex.color).collect::<Vec<_>>(), "VEC4", Some(TARGET_ARRAY_BUFFER), false).into();
+        }
+        if verts.iter().any(|vertex| vertex.weights != [0.0; 4]) {
+            let joints = verts.iter().flat_map(|vertex| vertex.joints.map(|joint| joint as u16)).collect::<Vec<_>>();
+            let buffer_view = buffer.buffer_view(bytemuck::cast_slice(&joints), Some(TARGET_ARRAY_BUFFER));
+            attributes["JOINTS_0"] = buffer.accessor(buffer_view, COMPONENT_TYPE_UNSIGNED_SHORT, verts.len(), "VEC4").into();
+            attributes["WEIGHTS_0"] = buffer.f32_accessor(&verts.iter().map(|vertex| vertex.weights).collect::<Vec<_>>(), "VEC4", Some(TARGET_ARRAY_BUFFER), false).into();
+        }
+
+        let mut primitive = json::object! {
+            "attributes": attributes,
+            "indices": buffer.index_accessor(&local_indices),
+        };
+        if submesh.topology == mesh::Topology::Points {
+            primitive["mode"] = mesh::PRIMITIVE_MODE_POINTS.into();
+        }
+        if let Some(material) = submesh.material {
+            primitive["material"] = material.into();
+        }
+        if !mesh.morph_targets.is_empty() {
+            let targets = mesh.morph_targets.iter().map(|morph_target| json::object! {
+                "POSITION": buffer.f32_accessor(&morph_target.positions[vertex_range.clone()], "VEC3", Some(TARGET_ARRAY_BUFFER), true),
+                "NORMAL": buffer.f32_accessor(&morph_target.normals[vertex_range.clone()], "VEC3", Some(TARGET_ARRAY_BUFFER), false),
+                "TANGENT": buffer.f32_accessor(&morph_target.tangents[vertex_range.clone()], "VEC3", Some(TARGET_ARRAY_BUFFER), false),
+            }).collect::<Vec<_>>();
+            primitive["targets"] = targets.into();
+        }
+        if !submesh.variant_materials.is_empty() {
+            // The loader flattens mappings to (variant, material) pairs, group them back per material
+            let mut mappings = Vec::<(usize, Vec<usize>)>::new();
+            for (variant, material) in &submesh.variant_materials {
+                match mappings.iter_mut().find(|(mapped_material, _)| mapped_material == material) {
+                    Some((_, variants)) => variants.push(*variant),
+                    None => mappings.push((*material, vec![*variant])),
+                }
+            }
+            let mappings = mappings.into_iter().map(|(material, variants)| json::object! { "material": material, "variants": variants }).collect::<Vec<_>>();
+            primitive["extensions"]["KHR_materials_variants"] = json::object! { "mappings": mappings };
+        }
+        primitives.push(primitive);
+    }
+    if primitives.is_empty() {
+        return Err(ExportError::EmptyMesh(index));
+    }
+    Ok(json::object! { "primitives": primitives })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Rotation3};
+
+    use super::*;
+    use crate::{camera, light, material, scene, skin};
+
+    /// Saves `scene` as .gltf and .glb under a fresh temporary directory and loads both back.
+    fn round_trip(scene: &Scene, name: &str) -> [Scene; 2] {
+        let directory = std::env::temp_dir().join(format!("webgpu_renderer_export_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let gltf_path = directory.join("scene.gltf");
+        let glb_path = directory.join("scene.glb");
+        save_gltf(scene, &gltf_path).unwrap();
+        save_glb(scene, &glb_path).unwrap();
+        let reloaded = [Scene::load_gltf(&gltf_path).unwrap(), Scene::load_gltf(&glb_path).unwrap()];
+        std::fs::remove_dir_all(&directory).unwrap();
+        reloaded
+    }
+
+    /// A node with nothing attached and a transform that isn't the identity.
+    fn node(name: &str) -> scene::Node {
+        scene::Node {
+            name: Some(name.to_string()),
+            children: Vec::new(),
+            mesh: None,
+            skin: None,
+            camera: None,
+            light: None,
+            translation: cgmath::vec3(1.0, 2.0, 3.0),
+            rotation: cgmath::Quaternion::from_axis_angle(cgmath::vec3(1.0, 1.0, 0.0).normalize(), cgmath::Deg(30.0)),
+            scale: cgmath::vec3(2.0, 2.0, 2.0),
+            weights: Vec::new(),
+            matrix: None,
+            instances: Vec::new(),
+        }
+    }
+
+    fn assert_meshes_equal(expected: &mesh::Mesh, actual: &mesh::Mesh) {
+        // Vertex is Pod, comparing the bytes compares every attribute exactly
+        assert_eq!(bytemuck::cast_slice::<_, u8>(&expected.verts), bytemuck::cast_slice::<_, u8>(&actual.verts));
+        // The index width may change on the way out, only the values matter
+        let indices = |mesh: &mesh::Mesh| (0..mesh.indices.len()).map(|i| mesh.indices.get(i)).collect::<Vec<_>>();
+        assert_eq!(indices(expected), indices(actual));
+        assert_eq!(expected.submeshes, actual.submeshes);
+        assert_eq!(expected.morph_targets, actual.morph_targets);
+    }
+
+    fn assert_scenes_equal(expected: &Scene, actual: &Scene) {
+        assert_eq!(expected.meshes.len(), actual.meshes.len());
+        for (expected_mesh, actual_mesh) in expected.meshes.iter().zip(&actual.meshes) {
+            assert_meshes_equal(expected_mesh, actual_mesh);
+        }
+        assert_eq!(expected.materials, actual.materials);
+        assert_eq!(expected.textures, actual.textures);
+        // Images are written as png, which keeps the decoded pixels
+        assert_eq!(expected.images.iter().map(|image| image.to_rgba8()).collect::<Vec<_>>(), actual.images.iter().map(|image| image.to_rgba8()).collect::<Vec<_>>());
+
+        assert_eq!(expected.roots, actual.roots);
+        assert_eq!(expected.nodes.len(), actual.nodes.len());
+        for (expected_node, actual_node) in expected.nodes.iter().zip(&actual.nodes) {
+            assert_eq!(expected_node.name, actual_node.name);
+            assert_eq!(expected_node.children, actual_node.children);
+            assert_eq!((expected_node.mesh, expected_node.skin, expected_node.camera, expected_node.light), (actual_node.mesh, actual_node.skin, actual_node.camera, actual_node.light));
+            assert_eq!(expected_node.translation, actual_node.translation);
+            // The loader normalizes rotations, which can move the last bit
+            assert!((expected_node.rotation - actual_node.rotation).magnitude() < 1e-6, "rotation {:?} became {:?}", expected_node.rotation, actual_node.rotation);
+            assert_eq!(expected_node.scale, actual_node.scale);
+            assert_eq!(expected_node.weights, actual_node.weights);
+            assert_eq!(expected_node.matrix, actual_node.matrix);
+        }
+
+        assert_eq!(expected.skins.len(), actual.skins.len());
+        for (expected_skin, actual_skin) in expected.skins.iter().zip(&actual.skins) {
+            assert_eq!(expected_skin.name, actual_skin.name);
+            assert_eq!(expected_skin.joints, actual_skin.joints);
+            assert_eq!(expected_skin.inverse_bind_matrices, actual_skin.inverse_bind_matrices);
+        }
+        assert_eq!(expected.cameras, actual.cameras);
+        assert_eq!(expected.lights, actual.lights);
+        assert_eq!(expected.variants, actual.variants);
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        for fixture in ["cube.glb", "monkey.gltf"] {
+            let scene = Scene::load_gltf(&std::path::Path::new("res").join(fixture)).unwrap();
+            for reloaded in round_trip(&scene, fixture) {
+                assert_scenes_equal(&scene, &reloaded);
+            }
+        }
+    }
+
+    #[test]
+    fn cameras_lights_skins_and_variants_round_trip() {
+        let mut scene = Scene::load_gltf(std::path::Path::new("res/cube.glb")).unwrap();
+        let mut variant_material = scene.materials[0].clone();
+        variant_material.name = Some("Red".to_string());
+        variant_material.base_color_factor = [1.0, 0.0, 0.0, 1.0];
+        scene.materials.push(variant_material);
+        scene.variants = vec!["Default".to_string(), "Red".to_string()];
+        scene.meshes[0].submeshes[0].variant_materials = vec![(1, 1)];
+
+        scene.cameras.push(camera::GltfCamera {
+            name: Some("Camera".to_string()),
+            projection: camera::Projection::Perspective,
+            aspect_ratio: Some(1.5),
+            yfov: 0.8,
+            znear: 0.1,
+            zfar: f32::INFINITY,
+        });
+        scene.lights.push(light::Light {
+            name: Some("Spot".to_string()),
+            kind: light::LightKind::Spot { inner_cone_angle: 0.25, outer_cone_angle: 0.5 },
+            color: [1.0, 0.5, 0.25],
+            intensity: 20.0,
+            range: Some(10.0),
+        });
+        scene.nodes.push(scene::Node { camera: Some(0), ..node("Camera") });
+        scene.nodes.push(scene::Node { light: Some(0), ..node("Spot") });
+        scene.nodes[0].children = vec![1, 2];
+        scene.skins.push(skin::Skin {
+            name: Some("Skin".to_string()),
+            joints: vec![1, 2],
+            inverse_bind_matrices: vec![cgmath::Matrix4::from_translation(cgmath::vec3(-1.0, -2.0, -3.0)), cgmath::Matrix4::from_scale(0.5)],
+        });
+
+        for reloaded in round_trip(&scene, "objects") {
+            assert_scenes_equal(&scene, &reloaded);
+        }
+    }
+
+    #[test]
+    fn scenes_without_binary_data_round_trip() {
+        let mut scene = Scene::default();
+        scene.materials.push(material::Material { name: Some("Plain".to_string()), ..Default::default() });
+        scene.cameras.push(camera::GltfCamera {
+            name: None,
+            projection: camera::Projection::Perspective,
+            aspect_ratio: None,
+            yfov: 0.8,
+            znear: 0.1,
+            zfar: 100.0,
+        });
+        scene.nodes.push(scene::Node { camera: Some(0), ..node("Camera") });
+        scene.roots = vec![0];
+        for reloaded in round_trip(&scene, "no_binary") {
+            assert_scenes_equal(&scene, &reloaded);
+        }
+
+        let directory = std::env::temp_dir().join(format!("webgpu_renderer_export_no_bin_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        save_gltf(&scene, &directory.join("scene.gltf")).unwrap();
+        let written = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name()).collect::<Vec<_>>();
+        assert_eq!(written, ["scene.gltf"]);
+        // The glb is just the header and the JSON chunk
+        let glb_path = directory.join("scene.glb");
+        save_glb(&scene, &glb_path).unwrap();
+        let glb = std::fs::read(&glb_path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(glb.len(), mesh::GLB_HEADER_LENGTH + mesh::GLB_CHUNK_HEADER_LENGTH + json_length);
+    }
+
+    /// Three colored points as a mesh with a single point submesh.
+    fn point_cloud() -> mesh::Mesh {
+        let verts = (0..3).map(|i| mesh::Vertex {
+            position: [i as f32, 0.5, -1.0],
+            normal: [0.0, 1.0, 0.0],
+            color: [0.25 * i as f32, 0.5, 1.0, 1.0],
+            ..Default::default()
+        }).collect::<Vec<_>>();
+        let submesh = mesh::Submesh { index_range: 0..3, topology: mesh::Topology::Points, base_vertex: 0, material: None, variant_materials: Vec::new() };
+        mesh::Mesh { indices: mesh::Indices::sequential(verts.len()), verts, submeshes: vec![submesh], morph_targets: Vec::new() }
+    }
+
+    #[test]
+    fn point_clouds_round_trip_as_points() {
+        let scene = Scene::from_mesh(point_cloud());
+        for reloaded in round_trip(&scene, "points") {
+            assert!(reloaded.meshes[0].is_point_cloud());
+            assert_scenes_equal(&scene, &reloaded);
+        }
+
+        let stl_path = std::env::temp_dir().join(format!("webgpu_renderer_export_points_{}.stl", std::process::id()));
+        assert!(matches!(save_stl(&scene.meshes[0], &stl_path), Err(ExportError::NoTriangles)));
+        assert!(!stl_path.exists());
+    }
+
+    #[test]
+    fn points_survive_merging_with_triangles() {
+        let mut scene = Scene::load_gltf(std::path::Path::new("res/cube.glb")).unwrap();
+        let cube = Scene::load_gltf(std::path::Path::new("res/cube.glb")).unwrap().meshes.remove(0);
+        let mut mixed = point_cloud();
+        mixed.append(cube);
+        scene.meshes[0].append(point_cloud());
+        scene.meshes.push(mixed);
+        scene.nodes.push(scene::Node { mesh: Some(1), ..node("Mixed") });
+        scene.roots.push(scene.nodes.len() - 1);
+        for reloaded in round_trip(&scene, "mixed") {
+            assert_scenes_equal(&scene, &reloaded);
+        }
+
+        // Mesh::load_gltf appends every mesh of the file into one
+        let directory = std::env::temp_dir().join(format!("webgpu_renderer_export_merged_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        save_glb(&scene, &directory.join("scene.glb")).unwrap();
+        let merged = mesh::Mesh::load_gltf(&directory.join("scene.glb"));
+        std::fs::remove_dir_all(&directory).unwrap();
+        let merged = merged.unwrap();
+        let points = merged.submeshes.iter().filter(|submesh| submesh.topology == mesh::Topology::Points).flat_map(|submesh| {
+            submesh.index_range.clone().map(|i| merged.verts[(merged.indices.get(i as usize) as i64 + submesh.base_vertex as i64) as usize].position)
+        }).collect::<Vec<_>>();
+        let expected = point_cloud().verts.iter().map(|vertex| vertex.position).collect::<Vec<_>>();
+        assert_eq!(points, [expected.clone(), expected].concat());
+    }
+}
@@ -1,28 +1,35 @@
 use camera::CameraUniform;
-use cgmath::Rotation3;
-use mesh::Instance;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder, dpi::PhysicalPosition,
 };
 use winit::window::Window;
-use std::{borrow::Cow, fs};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use wgpu::{util::DeviceExt, BindGroupLayout};
-use std::fs::File;
-use std::path::Path;
-use json;
 
-mod mesh;
-mod camera;
-mod texture;
+pub mod mesh;
+pub mod camera;
+pub mod texture;
+pub mod scene;
+pub mod material;
+pub mod skin;
+pub mod animation;
+pub mod morph;
+pub mod light;
+pub mod export;
+pub mod obj;
+pub mod stl;
+pub mod ply;
+pub mod splat;
 
 // Triangle
 #[allow(unused)]
 const TIANGLE_VERTICES: &[mesh::Vertex] = &[
-    mesh::Vertex { position: [0., 0.5, 0.],    color: [ 1., 1., 1.], uv: [0.5, 0.0] },
-    mesh::Vertex { position: [-0.5, -0.5, 0.], color: [ 1., 1., 1.], uv: [0.0, 1.0] },
-    mesh::Vertex { position: [0.5, -0.5, 0.],  color: [ 1., 1., 1.], uv: [1.0, 1.0] },
+    mesh::Vertex { position: [0., 0.5, 0.],    color: [ 1., 1., 1., 1.], uv: [0.5, 0.0], normal: [0., 0., 1.], tangent: [1., 0., 0., 1.], joints: [0; 4], weights: [0.; 4], uv1: [0.; 2] },
+    mesh::Vertex { position: [-0.5, -0.5, 0.], color: [ 1., 1., 1., 1.], uv: [0.0, 1.0], normal: [0., 0., 1.], tangent: [1., 0., 0., 1.], joints: [0; 4], weights: [0.; 4], uv1: [0.; 2] },
+    mesh::Vertex { position: [0.5, -0.5, 0.],  color: [ 1., 1., 1., 1.], uv: [1.0, 1.0], normal: [0., 0., 1.], tangent: [1., 0., 0., 1.], joints: [0; 4], weights: [0.; 4], uv1: [0.; 2] },
 ];
 #[allow(unused)]
 const TRIANGLE_INDICES: &[u16] = &[
@@ -30,19 +37,101 @@ const TRIANGLE_INDICES: &[u16] = &[
 ];
 
 // Trigonometic functions don't work in a const context :(
+#[allow(unused)]
 const PENTAGON_VERTICES: &[mesh::Vertex] = &[
-    mesh::Vertex { position: [ 0.0    * 0.5,  1.0   * 0.5, 0.0], color: [1.0, 1.0, 1.0], uv: [( 0.0 + 1.0) * 0.5,    1. - 1.0] },
-    mesh::Vertex { position: [ 0.951  * 0.5,  0.309 * 0.5, 0.0], color: [1.0, 1.0, 1.0], uv: [( 0.951 + 1.0) * 0.5,  1. - ( 0.309 + 1.0) * 0.5 ] },
-    mesh::Vertex { position: [ 0.5878 * 0.5, -0.809 * 0.5, 0.0], color: [1.0, 1.0, 1.0], uv: [( 0.5878 + 1.0) * 0.5, 1. - (-0.809 + 1.0) * 0.5 ] },
-    mesh::Vertex { position: [-0.5878 * 0.5, -0.809 * 0.5, 0.0], color: [1.0, 1.0, 1.0], uv: [(-0.5878 + 1.0) * 0.5, 1. - (-0.809 + 1.0) * 0.5 ] },
-    mesh::Vertex { position: [-0.951  * 0.5,  0.309 * 0.5, 0.0], color: [1.0, 1.0, 1.0], uv: [(-0.951 + 1.0) * 0.5,  1. - ( 0.309 + 1.0) * 0.5 ] },
+    mesh::Vertex { position: [ 0.0    * 0.5,  1.0   * 0.5, 0.0], color: [1.0, 1.0, 1.0, 1.0], uv: [( 0.0 + 1.0) * 0.5,    0.0], normal: [0.0, 0.0, 1.0], tangent: [1.0, 0.0, 0.0, 1.0], joints: [0; 4], weights: [0.0; 4], uv1: [0.0; 2] },
+    mesh::Vertex { position: [ 0.951  * 0.5,  0.309 * 0.5, 0.0], color: [1.0, 1.0, 1.0, 1.0], uv: [( 0.951 + 1.0) * 0.5,  1. - ( 0.309 + 1.0) * 0.5 ], normal: [0.0, 0.0, 1.0], tangent: [1.0, 0.0, 0.0, 1.0], joints: [0; 4], weights: [0.0; 4], uv1: [0.0; 2] },
+    mesh::Vertex { position: [ 0.5878 * 0.5, -0.809 * 0.5, 0.0], color: [1.0, 1.0, 1.0, 1.0], uv: [( 0.5878 + 1.0) * 0.5, 1. - (-0.809 + 1.0) * 0.5 ], normal: [0.0, 0.0, 1.0], tangent: [1.0, 0.0, 0.0, 1.0], joints: [0; 4], weights: [0.0; 4], uv1: [0.0; 2] },
+    mesh::Vertex { position: [-0.5878 * 0.5, -0.809 * 0.5, 0.0], color: [1.0, 1.0, 1.0, 1.0], uv: [(-0.5878 + 1.0) * 0.5, 1. - (-0.809 + 1.0) * 0.5 ], normal: [0.0, 0.0, 1.0], tangent: [1.0, 0.0, 0.0, 1.0], joints: [0; 4], weights: [0.0; 4], uv1: [0.0; 2] },
+    mesh::Vertex { position: [-0.951  * 0.5,  0.309 * 0.5, 0.0], color: [1.0, 1.0, 1.0, 1.0], uv: [(-0.951 + 1.0) * 0.5,  1. - ( 0.309 + 1.0) * 0.5 ], normal: [0.0, 0.0, 1.0], tangent: [1.0, 0.0, 0.0, 1.0], joints: [0; 4], weights: [0.0; 4], uv1: [0.0; 2] },
 ];
+#[allow(unused)]
 const PENTAGON_INDICES: &[u16] = &[
     0, 4, 1,
     4, 2, 1,
     4, 3, 2,
 ];
 
+/// Instances of a mesh that share a skin, drawn with a single draw call per submesh.
+struct MeshDraw {
+    mesh: usize,
+    /// Range of the instance buffer
+    instances: std::ops::Range<u32>,
+    /// Skin whose joint palette is bound, None for meshes that aren't skinned
+    skin: Option<usize>,
+    /// Index of the draw's morph weights, None for meshes without morph targets
+    morph: Option<usize>,
+}
+
+/// Groups the instances per mesh and skin so each group draws a single range of the returned instance data.
+/// Weights belong to nodes, so every instance of a mesh with morph targets is drawn on its own with its weights in the
+/// returned weight lists.
+fn build_mesh_draws(scene: &scene::Scene) -> (Vec<MeshDraw>, Vec<mesh::InstanceData>, Vec<Vec<f32>>) {
+    let mesh_instances = scene.mesh_instances();
+    let morph_node = |instance: &scene::MeshInstance| instance.node.filter(|_| !scene.meshes[instance.mesh].morph_targets.is_empty());
+    let mut draw_keys = mesh_instances.iter().map(|instance| (instance.mesh, instance.skin, morph_node(instance))).collect::<Vec<_>>();
+    draw_keys.sort();
+    draw_keys.dedup();
+    let mut instance_data = Vec::<mesh::InstanceData>::new();
+    let mut mesh_draws = Vec::new();
+    let mut morph_weights = Vec::new();
+    for (mesh, skin, node) in draw_keys {
+        let start = instance_data.len() as u32;
+        instance_data.extend(mesh_instances.iter().filter(|instance| instance.mesh == mesh && instance.skin == skin && morph_node(instance) == node).map(|instance| mesh::InstanceData::from(instance.transform)));
+        let morph = node.map(|node| {
+            // One weight per target no matter how many the node specifies
+            let mut weights = scene.nodes[node].weights.clone();
+            weights.resize(scene.meshes[mesh].morph_targets.len(), 0.0);
+            morph_weights.push(weights);
+            morph_weights.len() - 1
+        });
+        mesh_draws.push(MeshDraw { mesh, instances: start..instance_data.len() as u32, skin, morph });
+    }
+    (mesh_draws, instance_data, morph_weights)
+}
+
+/// The lights of the scene as the shader reads them, scenes without lights get the default sun so the list is never empty.
+fn build_light_uniforms(scene: &scene::Scene) -> Vec<light::LightUniform> {
+    let lights = scene.light_instances().iter()
+        .map(|instance| light::LightUniform::new(&scene.lights[instance.light], instance.transform))
+        .collect::<Vec<_>>();
+    if lights.is_empty() {
+        vec![light::LightUniform::default_sun()]
+    } else {
+        lights
+    }
+}
+
+/// Pipelines differ in vertex entry point, culling and blending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    skinned: bool,
+    morphed: bool,
+    double_sided: bool,
+    blend: bool,
+}
+
+/// The render passes submeshes are split into, in the order they are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DrawPass {
+    Opaque,
+    /// Opaque or masked but sampling the opaque scene behind it
+    Transmissive,
+    Blend,
+}
+
+impl DrawPass {
+    fn of(material: &material::GpuMaterial) -> Self {
+        if material.alpha_mode == material::AlphaMode::Blend {
+            DrawPass::Blend
+        } else if material.transmissive {
+            DrawPass::Transmissive
+        } else {
+            DrawPass::Opaque
+        }
+    }
+}
+
 struct State {
     surface: wgpu::Surface,
     device: wgpu::Device,
@@ -53,23 +142,39 @@ struct State {
     clear_color: wgpu::Color,
 
     render_pipeline: wgpu::RenderPipeline,
-    render_pipeline_2: wgpu::RenderPipeline,
-
-    mesh: mesh::Mesh,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
+    material_pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    splat_pipeline: wgpu::RenderPipeline,
+
+    scene: scene::Scene,
+    /// Plays the first animation of the scene, if it has any
+    animation_player: Option<animation::AnimationPlayer>,
+    last_update: std::time::Instant,
+    gpu_meshes: Vec<mesh::GpuMesh>,
+    /// One per `scene.materials`
+    gpu_materials: Vec<material::GpuMaterial>,
+    /// Used by submeshes without a material
+    default_material: material::GpuMaterial,
     instance_buffer: wgpu::Buffer,
-    instances: Vec<Instance>,
+    mesh_draws: Vec<MeshDraw>,
+    joint_palette: skin::JointPalette,
+    morph_weights: morph::MorphWeights,
+    point_splats: splat::PointSplats,
 
     orbit_camera: camera::OrbitCamera,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
+    light_buffer: wgpu::Buffer,
     prev_mouse_pos: PhysicalPosition<f64>,
 
     depth_texture: texture::Texture,
-    diffuse_texture: texture::Texture,
-    diffuse_bind_group: wgpu::BindGroup,
+    /// The opaque scene, sampled by transmissive materials
+    scene_color_texture: texture::Texture,
+    #[allow(unused)]
+    default_textures: material::DefaultTextures,
+    #[allow(unused)]
+    scene_textures: material::SceneTextures,
 
     space_pressed: bool,
     left_mouse_pressed: bool,
@@ -102,7 +207,11 @@ impl State {
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
                 features: wgpu::Features::empty(),
-                limits: wgpu::Limits::default(),
+                // Point clouds are bound as a single storage buffer, allow them to be as large as the hardware does
+                limits: wgpu::Limits {
+                    max_storage_buffer_binding_size: adapter.limits().max_storage_buffer_binding_size,
+                    ..Default::default()
+                },
                 label: None,
             }, None
         ).await.unwrap();
@@ -113,8 +222,10 @@ impl State {
             .copied()
             .find(|f| f.is_srgb())
             .unwrap_or(surface_capabilities.formats[0]);
+        // Transmission samples a copy of the surface, without COPY_SRC transmissive materials only see the clear color
+        let surface_usage = wgpu::TextureUsages::RENDER_ATTACHMENT | (surface_capabilities.usages & wgpu::TextureUsages::COPY_SRC);
         let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: surface_usage,
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -124,33 +235,11 @@ impl State {
         };
         surface.configure(&device, &surface_config);
 
-        // Load image data
-        let diffuse_bytes = include_bytes!("uv_checker.jpg");
-        let diffuse_texture = texture::Texture::from_memory(&device, &queue, diffuse_bytes, "uv_checker").unwrap();
-
-        let texture_bind_group_layout = device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
-                label: Some("Texture bind group layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    }
-                ],
-            }
-        );
+        let default_textures = material::DefaultTextures::new(&device, &queue);
+        let material_bind_group_layout = material::GpuMaterial::bind_group_layout(&device);
+        let joint_bind_group_layout = skin::JointPalette::bind_group_layout(&device);
+        let morph_bind_group_layout = morph::MorphWeights::bind_group_layout(&device);
+        let splat_bind_group_layout = splat::PointSplats::bind_group_layout(&device);
 
         let clear_color = wgpu::Color { r: 0.87, g: 0.87, b: 0.87, a: 1.0 };
 
@@ -162,6 +251,7 @@ impl State {
             fov_vertical: 45.,
             znear: 0.1,
             zfar: 100.,
+            projection: camera::Projection::Perspective,
         };
 
         let orbit_camera = camera::OrbitCamera::new(camera, cgmath::Point3 { x: 0., y: 0., z: 0. }, 2., 0., 0.);
@@ -183,78 +273,112 @@ impl State {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
                             min_binding_size: None,
                         },
                         count: None,
-                    }
-                ]
-            }
-        );
-
-        let depth_texture = texture::Texture::create_depth_texture(&device, &surface_config, "depth texture");
-        let render_pipeline = Self::create_render_pipeline(&device, &surface_config, include_str!("basic.wgsl").into(), "vs_main", "fs_main_2", &texture_bind_group_layout, &camera_bind_group_layout);
-        let render_pipeline_2 = Self::create_render_pipeline(&device, &surface_config, include_str!("basic.wgsl").into(), "vs_main_2", "fs_main", &texture_bind_group_layout, &camera_bind_group_layout);
-
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: Some("diffuse bind group"),
-                layout: &texture_bind_group_layout.into(),
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
                     },
-                    wgpu::BindGroupEntry {
+                    wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
                 ]
             }
         );
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Camera bind group"),
-            layout: &camera_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
+        let depth_texture = texture::Texture::create_depth_texture(&device, &surface_config, "depth texture");
+        let scene_color_texture = texture::Texture::create_scene_color_texture(&device, &surface_config, "scene color texture");
+        let render_pipeline = Self::create_render_pipeline(&device, &surface_config, include_str!("basic.wgsl").into(), "vs_main", "fs_main_2", &[&material_bind_group_layout, &camera_bind_group_layout], &[mesh::Vertex::desc(), mesh::InstanceData::desc()], Some(wgpu::Face::Back), false);
+        let mut material_pipelines = HashMap::new();
+        for skinned in [false, true] {
+            for morphed in [false, true] {
+                for double_sided in [false, true] {
+                    for blend in [false, true] {
+                        let cull_mode = if double_sided { None } else { Some(wgpu::Face::Back) };
+                        let vs_entry = match (skinned, morphed) {
+                            (false, false) => "vs_main_2",
+                            (true, false) => "vs_skinned",
+                            (false, true) => "vs_morphed",
+                            (true, true) => "vs_skinned_morphed",
+                        };
+                        // Morphed pipelines take the joint palette too so the morph targets always end up in group 3
+                        let mut bind_group_layouts = vec![&material_bind_group_layout, &camera_bind_group_layout];
+                        if skinned || morphed {
+                            bind_group_layouts.push(&joint_bind_group_layout);
+                        }
+                        if morphed {
+                            bind_group_layouts.push(&morph_bind_group_layout);
+                        }
+                        let pipeline = Self::create_render_pipeline(&device, &surface_config, include_str!("basic.wgsl").into(), vs_entry, "fs_main", &bind_group_layouts, &[mesh::Vertex::desc(), mesh::InstanceData::desc()], cull_mode, blend);
+                        material_pipelines.insert(PipelineKey { skinned, morphed, double_sided, blend }, pipeline);
+                    }
                 }
-            ]
-        });
+            }
+        }
+        // Splats are camera facing, the points come from a storage buffer so only the instances are vertex input
+        let splat_pipeline = Self::create_render_pipeline(&device, &surface_config, include_str!("splat.wgsl").into(), "vs_splat", "fs_splat", &[&splat_bind_group_layout, &camera_bind_group_layout], &[mesh::InstanceData::desc()], None, false);
+
 
         
         // Load glTF
-        let mesh = mesh::Mesh::load_gltf(&std::path::Path::new("./res/monkey.gltf"));
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex buffer"),
-            contents: bytemuck::cast_slice(&mesh.verts),
-            usage: wgpu::BufferUsages::VERTEX,
+        // A broken asset shouldn't take the viewer down, show an empty scene instead
+        let scene = scene::Scene::load_gltf(std::path::Path::new("./res/monkey.gltf")).unwrap_or_else(|error| {
+            log::error!("Failed to load glTF: {}", error);
+            scene::Scene::default()
         });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index buffer"),
-            contents: bytemuck::cast_slice(&mesh.indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-
-        let instances = (0..10).flat_map(|x| {
-            (0..10).map(move |z| {
-                let position = cgmath::Point3 { x: x as f32 - 5., y: 0., z: z as f32 - 5. };
-                let rotation = cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(x as f32 * z as f32));
-                Instance { position, rotation }
-            })
+        let gpu_meshes = scene.meshes.iter().map(|mesh| mesh::GpuMesh::new(&device, mesh)).collect::<Vec<_>>();
+        let scene_textures = material::SceneTextures::new(&device, &queue, &scene.images, &scene.textures, &scene.materials);
+        let gpu_materials = scene.materials.iter().map(|material| {
+            material::GpuMaterial::new(&device, &material_bind_group_layout, material, &scene_textures.material_textures(material, &default_textures))
         }).collect::<Vec<_>>();
-        let instance_data = instances.iter().map(Instance::to_data).collect::<Vec<_>>();
+        let default_material = material::GpuMaterial::new(&device, &material_bind_group_layout, &material::Material::default(), &default_textures.material_textures());
+
+        // Every node that references a mesh becomes an instance
+        let (mesh_draws, instance_data, morph_weights) = build_mesh_draws(&scene);
+        let joint_palette = skin::JointPalette::new(&device, &joint_bind_group_layout, &scene.joint_matrices());
+        let morph_weights = morph::MorphWeights::new(&device, &morph_bind_group_layout, &gpu_meshes, &morph_weights);
+        let point_splats = splat::PointSplats::new(&device, &splat_bind_group_layout, &gpu_meshes, size);
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light buffer"),
+            contents: bytemuck::cast_slice(&build_light_uniforms(&scene)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = Self::create_camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer, &scene_color_texture, &light_buffer);
+
+        let animation_player = (!scene.animations.is_empty()).then(|| {
+            let mut player = animation::AnimationPlayer::new(&scene, 0);
+            player.play();
+            player
         });
 
         Self {
@@ -267,23 +391,33 @@ impl State {
             clear_color,
 
             render_pipeline,
-            render_pipeline_2,
-
-            mesh,
-            vertex_buffer,
-            index_buffer,
-            instances,
+            material_pipelines,
+            splat_pipeline,
+
+            scene,
+            animation_player,
+            last_update: std::time::Instant::now(),
+            gpu_meshes,
+            gpu_materials,
+            default_material,
             instance_buffer,
+            mesh_draws,
+            joint_palette,
+            morph_weights,
+            point_splats,
 
             orbit_camera,
             camera_uniform,
             camera_buffer,
+            camera_bind_group_layout,
             camera_bind_group,
+            light_buffer,
             prev_mouse_pos: PhysicalPosition { x: -1., y: -1. },
 
             depth_texture,
-            diffuse_texture,
-            diffuse_bind_group,
+            scene_color_texture,
+            default_textures,
+            scene_textures,
             space_pressed: false,
             left_mouse_pressed: false,
             right_mouse_pressed: false,
@@ -294,7 +428,8 @@ impl State {
         &self.window
     }
 
-    fn create_render_pipeline(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration, shader: Cow<'_, str>, vs_entry: &str, fs_entry: &str, texture_bind_group_layout: &BindGroupLayout, camera_bind_group_layout: &BindGroupLayout) -> wgpu::RenderPipeline {
+    #[allow(clippy::too_many_arguments)]
+    fn create_render_pipeline(device: &wgpu::Device, surface_config: &wgpu::SurfaceConfiguration, shader: Cow<'_, str>, vs_entry: &str, fs_entry: &str, bind_group_layouts: &[&BindGroupLayout], buffers: &[wgpu::VertexBufferLayout], cull_mode: Option<wgpu::Face>, blend: bool) -> wgpu::RenderPipeline {
         // Real code to create a shader
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Basic Shader"),
@@ -305,29 +440,24 @@ impl State {
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[
-                &texture_bind_group_layout,
-                &camera_bind_group_layout
-            ],
+            bind_group_layouts,
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: vs_entry,
-                buffers: &[
-                    mesh::Vertex::desc(), mesh::InstanceData::desc(),
-                ],
+                buffers,
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
                 entry_point: fs_entry,
                 targets: &[Some(wgpu::ColorTargetState {
                     format: surface_config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(if blend { wgpu::BlendState::ALPHA_BLENDING } else { wgpu::BlendState::REPLACE }),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -335,14 +465,15 @@ impl State {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                cull_mode,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                // Blended surfaces are drawn after the opaque ones and must not hide each other
+                depth_write_enabled: !blend,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
@@ -353,8 +484,32 @@ impl State {
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
-        render_pipeline
+        })
+    }
+
+    fn create_camera_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, camera_buffer: &wgpu::Buffer, scene_color_texture: &texture::Texture, light_buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&scene_color_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&scene_color_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: light_buffer.as_entire_binding(),
+                },
+            ]
+        })
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -365,32 +520,24 @@ impl State {
             self.surface.configure(&self.device, &self.surface_config)
         }
         self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.surface_config, "depth texture");
+        self.scene_color_texture = texture::Texture::create_scene_color_texture(&self.device, &self.surface_config, "scene color texture");
+        self.camera_bind_group = Self::create_camera_bind_group(&self.device, &self.camera_bind_group_layout, &self.camera_buffer, &self.scene_color_texture, &self.light_buffer);
+        self.point_splats.resize(&self.queue, self.size);
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::MouseInput { device_id: _, state, button, .. } => {
                 if button == &MouseButton::Left {
-                    if state == &ElementState::Pressed {
-                        self.left_mouse_pressed = true;
-                    } else {
-                        self.left_mouse_pressed = false;
-                    }
+                    self.left_mouse_pressed = state == &ElementState::Pressed;
                 } else if button == &MouseButton::Right {
-                    if state == &ElementState::Pressed {
-                        self.right_mouse_pressed = true;
-                    } else {
-                        self.right_mouse_pressed = false;
-                    }
+                    self.right_mouse_pressed = state == &ElementState::Pressed;
                 }
                 true
             },
             WindowEvent::MouseWheel { device_id: _, delta, phase: _, .. } => {
-                match delta {
-                    MouseScrollDelta::LineDelta(_h, v) => {
-                        self.orbit_camera.handle_scroll(*v)
-                    }
-                    _ => ()
+                if let MouseScrollDelta::LineDelta(_h, v) = delta {
+                    self.orbit_camera.handle_scroll(*v)
                 }
                 true
             }
@@ -413,6 +560,27 @@ impl State {
                     self.space_pressed = input.state == winit::event::ElementState::Pressed;
                     return true
                 }
+                // V cycles through the material variants, ending on the default materials
+                if input.virtual_keycode == Some(winit::event::VirtualKeyCode::V) && input.state == winit::event::ElementState::Pressed {
+                    self.scene.active_variant = match self.scene.active_variant {
+                        None if !self.scene.variants.is_empty() => Some(0),
+                        Some(variant) if variant + 1 < self.scene.variants.len() => Some(variant + 1),
+                        _ => None,
+                    };
+                    return true
+                }
+                // C cycles through the glTF cameras, ending on the orbit camera
+                if input.virtual_keycode == Some(winit::event::VirtualKeyCode::C) && input.state == winit::event::ElementState::Pressed {
+                    // Cameras no node places can't be viewed through and are skipped
+                    let mut next = self.scene.active_camera.map_or(0, |camera| camera + 1);
+                    while next < self.scene.cameras.len() && !self.scene.select_camera(Some(next)) {
+                        next += 1;
+                    }
+                    if next >= self.scene.cameras.len() {
+                        self.scene.select_camera(None);
+                    }
+                    return true
+                }
                 false
             }
             _ => false
@@ -420,10 +588,71 @@ impl State {
     }
 
     fn update(&mut self) {
-        self.camera_uniform.update_view_projection(&self.orbit_camera.camera());
+        let now = std::time::Instant::now();
+        let delta_time = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+        if let Some(animation_player) = &mut self.animation_player {
+            animation_player.update(&mut self.scene, delta_time);
+        }
+        // Node transforms and weights may have been changed by the animation player or from code. The draws only
+        // depend on which nodes have meshes, so they stay the same and only the buffer contents change
+        let (_, instance_data, morph_weights) = build_mesh_draws(&self.scene);
+        self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+        self.joint_palette.update(&self.device, &self.queue, &self.scene.joint_matrices());
+        self.morph_weights.update(&self.device, &self.queue, &morph_weights);
+        self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&build_light_uniforms(&self.scene)));
+
+        // glTF cameras follow their nodes, so they are placed again every frame
+        let aspect_ratio = self.size.width as f32 / self.size.height as f32;
+        match self.scene.active_camera.and_then(|camera| self.scene.camera(camera, aspect_ratio)) {
+            Some(camera) => self.camera_uniform.update_view_projection(&camera),
+            None => self.camera_uniform.update_view_projection(self.orbit_camera.camera()),
+        }
         self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
     }
 
+    /// Draws every submesh whose material belongs in `pass`.
+    fn draw_submeshes<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, pass: DrawPass) {
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        for draw in &self.mesh_draws {
+            let gpu_mesh = &self.gpu_meshes[draw.mesh];
+            // Point submeshes are drawn as splats without a material, they are opaque
+            if let Some(bind_group) = &self.point_splats.bind_groups[draw.mesh] {
+                if pass == DrawPass::Opaque {
+                    render_pass.set_pipeline(&self.splat_pipeline);
+                    render_pass.set_bind_group(0, bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+                    render_pass.draw(0..gpu_mesh.splat_count * splat::PointSplats::VERTICES_PER_POINT, draw.instances.clone());
+                }
+            }
+            render_pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_index_buffer(gpu_mesh.index_buffer.slice(..), gpu_mesh.index_format);
+            if draw.skin.is_some() || draw.morph.is_some() {
+                // Morphed meshes without a skin don't read the palette, any offset will do
+                let offset = draw.skin.map_or(0, |skin| self.joint_palette.offsets[skin]);
+                render_pass.set_bind_group(2, &self.joint_palette.bind_group, &[offset]);
+            }
+            if let (Some(morph), Some(bind_group)) = (draw.morph, &self.morph_weights.bind_groups[draw.mesh]) {
+                render_pass.set_bind_group(3, bind_group, &[self.morph_weights.offsets[morph]]);
+            }
+            for submesh in gpu_mesh.submeshes.iter().filter(|submesh| submesh.topology == mesh::Topology::Triangles) {
+                let material = submesh.material_for_variant(self.scene.active_variant).and_then(|index| self.gpu_materials.get(index)).unwrap_or(&self.default_material);
+                if DrawPass::of(material) != pass {
+                    continue;
+                }
+                if self.space_pressed {
+                    render_pass.set_pipeline(&self.render_pipeline);
+                } else {
+                    let key = PipelineKey { skinned: draw.skin.is_some(), morphed: draw.morph.is_some(), double_sided: material.double_sided, blend: pass == DrawPass::Blend };
+                    render_pass.set_pipeline(&self.material_pipelines[&key]);
+                }
+                render_pass.set_bind_group(0, &material.bind_group, &[]);
+                render_pass.draw_indexed(submesh.index_range.clone(), submesh.base_vertex, draw.instances.clone());
+            }
+        }
+    }
+
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -433,7 +662,7 @@ impl State {
         // Extra scope so that _render_pass is dropped since it borrows mutable encoder
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
+                label: Some("Opaque Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view: &view,
                     resolve_target: None,
@@ -451,18 +680,37 @@ impl State {
                     stencil_ops: None,
                 }),
             });
+            self.draw_submeshes(&mut render_pass, DrawPass::Opaque);
+        }
 
-            if self.space_pressed == true {
-                render_pass.set_pipeline(&self.render_pipeline);
-            } else {
-                render_pass.set_pipeline(&self.render_pipeline_2);
-            }
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.mesh.indices.len() as u32, 0, 0..1 as _);
+        // Transmissive materials refract whatever the opaque pass left behind them
+        if self.surface_config.usage.contains(wgpu::TextureUsages::COPY_SRC) && self.gpu_materials.iter().any(|material| material.transmissive) {
+            encoder.copy_texture_to_texture(output.texture.as_image_copy(), self.scene_color_texture.texture.as_image_copy(), output.texture.size());
+        }
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transmission and Blend Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            // Blended submeshes go last so they land on top of transmissive ones
+            self.draw_submeshes(&mut render_pass, DrawPass::Transmissive);
+            self.draw_submeshes(&mut render_pass, DrawPass::Blend);
         }
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
@@ -482,7 +730,7 @@ pub async fn run() {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == state.window().id() => if !state.input(event) {
+        } if window_id == state.window().id() && !state.input(event) => {
             match event {
                 WindowEvent::CloseRequested
                 | WindowEvent::KeyboardInput {
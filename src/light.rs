@@ -0,0 +1,140 @@
+use cgmath::InnerSpace;
+
+use crate::mesh::{self, GltfError};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    /// Cone angles in radians, measured from the spot's axis
+    Spot { inner_cone_angle: f32, outer_cone_angle: f32 },
+}
+
+/// A KHR_lights_punctual light, it only shines once a node places it in the scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Light {
+    pub name: Option<String>,
+    pub kind: LightKind,
+    /// Linear rgb
+    pub color: [f32; 3],
+    /// Lux for directional lights, candela for point and spot lights
+    pub intensity: f32,
+    /// Distance at which the light has faded out completely, None for the inverse square falloff alone
+    pub range: Option<f32>,
+}
+
+impl Light {
+    fn from_gltf(light: &json::JsonValue, index: usize) -> Result<Self, GltfError> {
+        let object = format!("extensions.KHR_lights_punctual.lights[{}]", index);
+        let kind = match mesh::required_str(light, &object, "type")? {
+            "directional" => LightKind::Directional,
+            "point" => LightKind::Point,
+            "spot" => {
+                let spot = &light["spot"];
+                LightKind::Spot {
+                    inner_cone_angle: spot["innerConeAngle"].as_f32().unwrap_or(0.0),
+                    outer_cone_angle: spot["outerConeAngle"].as_f32().unwrap_or(std::f32::consts::FRAC_PI_4),
+                }
+            }
+            _ => return Err(GltfError::MissingProperty { object, property: "type" }),
+        };
+        Ok(Self {
+            name: light["name"].as_str().map(String::from),
+            kind,
+            color: mesh::optional_f32_array(light, &object, "color")?.unwrap_or([1.0, 1.0, 1.0]),
+            intensity: light["intensity"].as_f32().unwrap_or(1.0),
+            // Directional lights have no range
+            range: light["range"].as_f32().filter(|_| kind != LightKind::Directional),
+        })
+    }
+
+    /// The light object `from_gltf` reads.
+    pub(crate) fn to_gltf(&self) -> json::JsonValue {
+        let mut light = json::object! {
+            "color": self.color.to_vec(),
+            "intensity": self.intensity,
+        };
+        match self.kind {
+            LightKind::Directional => light["type"] = "directional".into(),
+            LightKind::Point => light["type"] = "point".into(),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                light["type"] = "spot".into();
+                light["spot"] = json::object! { "innerConeAngle": inner_cone_angle, "outerConeAngle": outer_cone_angle };
+            }
+        }
+        if let Some(name) = &self.name {
+            light["name"] = name.as_str().into();
+        }
+        if let Some(range) = self.range {
+            light["range"] = range.into();
+        }
+        light
+    }
+
+    /// Loads the lights of the KHR_lights_punctual extension, in order so node light indices refer into the result.
+    pub(crate) fn all_from_gltf(gltf: &mesh::GltfDocument) -> Result<Vec<Self>, GltfError> {
+        gltf.json["extensions"]["KHR_lights_punctual"]["lights"].members().enumerate().map(|(i, light)| Self::from_gltf(light, i)).collect()
+    }
+}
+
+const LIGHT_DIRECTIONAL: u32 = 0;
+const LIGHT_POINT: u32 = 1;
+const LIGHT_SPOT: u32 = 2;
+
+/// A placed light as laid out in the shader's light list.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    /// World space, w unused
+    position: [f32; 4],
+    /// World space direction the light travels in, w unused
+    direction: [f32; 4],
+    /// Color multiplied with intensity, w unused
+    color: [f32; 4],
+    kind: u32,
+    /// 0 for unlimited range
+    range: f32,
+    /// Map the cosine of the angle to the spot's axis onto its 0..1 falloff
+    spot_scale: f32,
+    spot_offset: f32,
+}
+
+impl LightUniform {
+    /// Places `light` with a node's world transform, glTF lights shine down their node's -Z.
+    pub fn new(light: &Light, world_transform: cgmath::Matrix4<f32>) -> Self {
+        let position = world_transform * cgmath::vec4(0.0, 0.0, 0.0, 1.0);
+        let direction = (world_transform * cgmath::Vector4::unit_z() * -1.0).truncate().normalize();
+        let [r, g, b] = light.color.map(|component| component * light.intensity);
+        let (kind, spot_scale, spot_offset) = match light.kind {
+            LightKind::Directional => (LIGHT_DIRECTIONAL, 0.0, 0.0),
+            LightKind::Point => (LIGHT_POINT, 0.0, 0.0),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                let spot_scale = 1.0 / (inner_cone_angle.cos() - outer_cone_angle.cos()).max(0.001);
+                (LIGHT_SPOT, spot_scale, -outer_cone_angle.cos() * spot_scale)
+            }
+        };
+        Self {
+            position: position.truncate().extend(0.0).into(),
+            direction: direction.extend(0.0).into(),
+            color: [r, g, b, 0.0],
+            kind,
+            range: light.range.unwrap_or(0.0),
+            spot_scale,
+            spot_offset,
+        }
+    }
+
+    /// Lights scenes that don't bring any lights of their own.
+    pub fn default_sun() -> Self {
+        let direction = -cgmath::vec3(0.4, 0.8, 0.45).normalize();
+        Self {
+            position: [0.0; 4],
+            direction: direction.extend(0.0).into(),
+            color: [3.0, 3.0, 3.0, 0.0],
+            kind: LIGHT_DIRECTIONAL,
+            range: 0.0,
+            spot_scale: 0.0,
+            spot_offset: 0.0,
+        }
+    }
+}
@@ -0,0 +1,918 @@
+use std::collections::HashMap;
+
+use json::JsonValue;
+use wgpu::util::DeviceExt;
+
+use crate::mesh::{self, GltfError};
+use crate::texture;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fully opaque or fully transparent depending on `Material::alpha_cutoff`
+    Mask,
+    Blend,
+}
+
+/// KHR_texture_transform, applied to the uvs as offset * rotation * scale.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureTransform {
+    pub offset: [f32; 2],
+    /// Counter-clockwise in radians
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+impl TextureTransform {
+    /// The two rows of the 3x3 uv matrix, the last row is always 0, 0, 1. Each row is padded to a vec4 for the shader.
+    pub fn rows(&self) -> [[f32; 4]; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [sx, sy] = self.scale;
+        let [ox, oy] = self.offset;
+        [
+            [cos * sx, sin * sy, ox, 0.0],
+            [-sin * sx, cos * sy, oy, 0.0],
+        ]
+    }
+}
+
+/// Reference to a glTF texture from a material slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureRef {
+    /// Index into the glTF textures
+    pub texture: usize,
+    /// Which TEXCOORD_n set the texture uses, `mesh::Vertex` holds TEXCOORD_0 and TEXCOORD_1
+    pub tex_coord: u32,
+    pub transform: TextureTransform,
+}
+
+impl TextureRef {
+    fn from_gltf(value: &JsonValue, object: &str, property: &'static str) -> Result<Option<Self>, GltfError> {
+        let texture_info = &value[property];
+        if texture_info.is_null() {
+            return Ok(None);
+        }
+        let object = format!("{}.{}", object, property);
+        let mut tex_coord = texture_info["texCoord"].as_u32().unwrap_or(0);
+
+        let transform_extension = &texture_info["extensions"]["KHR_texture_transform"];
+        let transform_object = format!("{}.extensions.KHR_texture_transform", object);
+        let defaults = TextureTransform::default();
+        let transform = TextureTransform {
+            offset: mesh::optional_f32_array(transform_extension, &transform_object, "offset")?.unwrap_or(defaults.offset),
+            rotation: transform_extension["rotation"].as_f32().unwrap_or(defaults.rotation),
+            scale: mesh::optional_f32_array(transform_extension, &transform_object, "scale")?.unwrap_or(defaults.scale),
+        };
+        // The extension may move the texture to another uv set
+        if let Some(transform_tex_coord) = transform_extension["texCoord"].as_u32() {
+            tex_coord = transform_tex_coord;
+        }
+
+        Ok(Some(Self {
+            texture: mesh::required_usize(texture_info, &object, "index")?,
+            tex_coord,
+            transform,
+        }))
+    }
+
+    /// The textureInfo object `from_gltf` reads.
+    pub(crate) fn to_gltf(&self) -> JsonValue {
+        let mut texture_info = json::object! { "index": self.texture };
+        if self.tex_coord != 0 {
+            texture_info["texCoord"] = self.tex_coord.into();
+        }
+        if self.transform != TextureTransform::default() {
+            texture_info["extensions"]["KHR_texture_transform"] = json::object! {
+                "offset": self.transform.offset.to_vec(),
+                "rotation": self.transform.rotation,
+                "scale": self.transform.scale.to_vec(),
+            };
+        }
+        texture_info
+    }
+}
+
+// glTF sampler enums, the values are the OpenGL ones
+const FILTER_NEAREST: u32 = 9728;
+const FILTER_LINEAR: u32 = 9729;
+const FILTER_NEAREST_MIPMAP_NEAREST: u32 = 9984;
+const FILTER_LINEAR_MIPMAP_NEAREST: u32 = 9985;
+const FILTER_NEAREST_MIPMAP_LINEAR: u32 = 9986;
+const FILTER_LINEAR_MIPMAP_LINEAR: u32 = 9987;
+const WRAP_CLAMP_TO_EDGE: u32 = 33071;
+const WRAP_MIRRORED_REPEAT: u32 = 33648;
+const WRAP_REPEAT: u32 = 10497;
+
+/// Filtering and wrapping of a glTF sampler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampler {
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    /// None when the min filter doesn't use mipmaps
+    pub mipmap_filter: Option<wgpu::FilterMode>,
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+}
+
+impl Default for Sampler {
+    /// Used for textures without a sampler and for filters the file leaves up to us.
+    fn default() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: Some(wgpu::FilterMode::Linear),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+        }
+    }
+}
+
+impl Sampler {
+    pub(crate) fn from_gltf(sampler: &JsonValue, index: usize) -> Result<Self, GltfError> {
+        let error = |property: &str, value: u32| GltfError::Sampler { index, reason: format!("invalid {} {}", property, value) };
+        let defaults = Self::default();
+
+        let mag_filter = match sampler["magFilter"].as_u32() {
+            None => defaults.mag_filter,
+            Some(FILTER_NEAREST) => wgpu::FilterMode::Nearest,
+            Some(FILTER_LINEAR) => wgpu::FilterMode::Linear,
+            Some(value) => return Err(error("magFilter", value)),
+        };
+        let (min_filter, mipmap_filter) = match sampler["minFilter"].as_u32() {
+            None => (defaults.min_filter, defaults.mipmap_filter),
+            Some(FILTER_NEAREST) => (wgpu::FilterMode::Nearest, None),
+            Some(FILTER_LINEAR) => (wgpu::FilterMode::Linear, None),
+            Some(FILTER_NEAREST_MIPMAP_NEAREST) => (wgpu::FilterMode::Nearest, Some(wgpu::FilterMode::Nearest)),
+            Some(FILTER_LINEAR_MIPMAP_NEAREST) => (wgpu::FilterMode::Linear, Some(wgpu::FilterMode::Nearest)),
+            Some(FILTER_NEAREST_MIPMAP_LINEAR) => (wgpu::FilterMode::Nearest, Some(wgpu::FilterMode::Linear)),
+            Some(FILTER_LINEAR_MIPMAP_LINEAR) => (wgpu::FilterMode::Linear, Some(wgpu::FilterMode::Linear)),
+            Some(value) => return Err(error("minFilter", value)),
+        };
+        let address_mode = |property: &str| match sampler[property].as_u32() {
+            None | Some(WRAP_REPEAT) => Ok(wgpu::AddressMode::Repeat),
+            Some(WRAP_CLAMP_TO_EDGE) => Ok(wgpu::AddressMode::ClampToEdge),
+            Some(WRAP_MIRRORED_REPEAT) => Ok(wgpu::AddressMode::MirrorRepeat),
+            Some(value) => Err(error(property, value)),
+        };
+
+        Ok(Self {
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+            address_mode_u: address_mode("wrapS")?,
+            address_mode_v: address_mode("wrapT")?,
+        })
+    }
+
+    pub(crate) fn to_gltf(self) -> JsonValue {
+        let filter = |filter| match filter {
+            wgpu::FilterMode::Nearest => FILTER_NEAREST,
+            wgpu::FilterMode::Linear => FILTER_LINEAR,
+        };
+        let min_filter = match (self.min_filter, self.mipmap_filter) {
+            (min_filter, None) => filter(min_filter),
+            (wgpu::FilterMode::Nearest, Some(wgpu::FilterMode::Nearest)) => FILTER_NEAREST_MIPMAP_NEAREST,
+            (wgpu::FilterMode::Linear, Some(wgpu::FilterMode::Nearest)) => FILTER_LINEAR_MIPMAP_NEAREST,
+            (wgpu::FilterMode::Nearest, Some(wgpu::FilterMode::Linear)) => FILTER_NEAREST_MIPMAP_LINEAR,
+            (wgpu::FilterMode::Linear, Some(wgpu::FilterMode::Linear)) => FILTER_LINEAR_MIPMAP_LINEAR,
+        };
+        // wgpu has a border color mode glTF can't express, it is closest to clamping
+        let wrap = |address_mode| match address_mode {
+            wgpu::AddressMode::Repeat => WRAP_REPEAT,
+            wgpu::AddressMode::MirrorRepeat => WRAP_MIRRORED_REPEAT,
+            wgpu::AddressMode::ClampToEdge | wgpu::AddressMode::ClampToBorder => WRAP_CLAMP_TO_EDGE,
+        };
+        json::object! {
+            "magFilter": filter(self.mag_filter),
+            "minFilter": min_filter,
+            "wrapS": wrap(self.address_mode_u),
+            "wrapT": wrap(self.address_mode_v),
+        }
+    }
+
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter.unwrap_or(wgpu::FilterMode::Nearest),
+            // Without mipmapping only the full size level may be sampled
+            lod_max_clamp: if self.mipmap_filter.is_some() { 32.0 } else { 0.0 },
+            ..Default::default()
+        }
+    }
+}
+
+/// A glTF texture, an image and how to sample it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTexture {
+    /// Index into the glTF images
+    pub image: usize,
+    pub sampler: Sampler,
+}
+
+impl ImageTexture {
+    /// Loads all textures of a glTF file, checking that their images and samplers exist.
+    pub(crate) fn all_from_gltf(gltf: &mesh::GltfDocument) -> Result<Vec<Self>, GltfError> {
+        let samplers = gltf.json["samplers"].members().enumerate().map(|(i, sampler)| Sampler::from_gltf(sampler, i)).collect::<Result<Vec<_>, _>>()?;
+        let image_count = gltf.json["images"].len();
+        let mut textures = Vec::new();
+        for (index, texture) in gltf.json["textures"].members().enumerate() {
+            let image = mesh::required_usize(texture, &format!("textures[{}]", index), "source")?;
+            if image >= image_count {
+                return Err(GltfError::Texture { index, reason: format!("image {} does not exist", image) });
+            }
+            let sampler = match texture["sampler"].as_usize() {
+                None => Sampler::default(),
+                Some(sampler) => *samplers.get(sampler).ok_or_else(|| GltfError::Texture { index, reason: format!("sampler {} does not exist", sampler) })?,
+            };
+            textures.push(Self { image, sampler });
+        }
+        Ok(textures)
+    }
+}
+
+/// Decodes a glTF image, stored either behind a uri or in a bufferView.
+pub(crate) fn load_image(gltf: &mesh::GltfDocument, index: usize) -> Result<image::DynamicImage, GltfError> {
+    let image = &gltf.json["images"][index];
+    let object = format!("images[{}]", index);
+    let uri_data;
+    let data = match image["uri"].as_str() {
+        Some(uri) => {
+            uri_data = mesh::read_uri(&gltf.path, uri)?;
+            &uri_data[..]
+        }
+        // mimeType is required for images stored in a bufferView
+        None => {
+            mesh::required_str(image, &object, "mimeType")?;
+            gltf.buffer_view_data(mesh::required_usize(image, &object, "bufferView")?)?
+        }
+    };
+    let format = match image["mimeType"].as_str() {
+        Some("image/png") => Some(image::ImageFormat::Png),
+        Some("image/jpeg") => Some(image::ImageFormat::Jpeg),
+        Some(mime_type) => return Err(GltfError::Image { index, reason: format!("unsupported mimeType {}", mime_type) }),
+        // Files referenced by uri don't need a mimeType, go by their contents
+        None => None,
+    };
+    let decoded = match format {
+        Some(format) => image::load_from_memory_with_format(data, format),
+        None => image::load_from_memory(data),
+    };
+    decoded.map_err(|error| GltfError::Image { index, reason: error.to_string() })
+}
+
+/// KHR_materials_clearcoat, a second specular layer on top of the base material.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clearcoat {
+    pub factor: f32,
+    /// Intensity in the red channel
+    pub texture: Option<TextureRef>,
+    pub roughness_factor: f32,
+    /// Roughness in the green channel
+    pub roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+}
+
+impl Clearcoat {
+    fn from_gltf(extensions: &JsonValue, object: &str) -> Result<Option<Self>, GltfError> {
+        let extension = &extensions["KHR_materials_clearcoat"];
+        if !extension.is_object() {
+            return Ok(None);
+        }
+        let object = format!("{}.extensions.KHR_materials_clearcoat", object);
+        Ok(Some(Self {
+            factor: extension["clearcoatFactor"].as_f32().unwrap_or(0.0),
+            texture: TextureRef::from_gltf(extension, &object, "clearcoatTexture")?,
+            roughness_factor: extension["clearcoatRoughnessFactor"].as_f32().unwrap_or(0.0),
+            roughness_texture: TextureRef::from_gltf(extension, &object, "clearcoatRoughnessTexture")?,
+            normal_texture: TextureRef::from_gltf(extension, &object, "clearcoatNormalTexture")?,
+            normal_scale: extension["clearcoatNormalTexture"]["scale"].as_f32().unwrap_or(1.0),
+        }))
+    }
+}
+
+/// KHR_materials_sheen, back-scattering of cloth-like microfibers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sheen {
+    pub color_factor: [f32; 3],
+    pub color_texture: Option<TextureRef>,
+    pub roughness_factor: f32,
+    /// Roughness in the alpha channel
+    pub roughness_texture: Option<TextureRef>,
+}
+
+impl Sheen {
+    fn from_gltf(extensions: &JsonValue, object: &str) -> Result<Option<Self>, GltfError> {
+        let extension = &extensions["KHR_materials_sheen"];
+        if !extension.is_object() {
+            return Ok(None);
+        }
+        let object = format!("{}.extensions.KHR_materials_sheen", object);
+        Ok(Some(Self {
+            color_factor: mesh::optional_f32_array(extension, &object, "sheenColorFactor")?.unwrap_or([0.0; 3]),
+            color_texture: TextureRef::from_gltf(extension, &object, "sheenColorTexture")?,
+            roughness_factor: extension["sheenRoughnessFactor"].as_f32().unwrap_or(0.0),
+            roughness_texture: TextureRef::from_gltf(extension, &object, "sheenRoughnessTexture")?,
+        }))
+    }
+}
+
+/// KHR_materials_transmission, how much light passes through the surface instead of being diffused.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transmission {
+    pub factor: f32,
+    /// Transmission in the red channel
+    pub texture: Option<TextureRef>,
+}
+
+impl Transmission {
+    fn from_gltf(extensions: &JsonValue, object: &str) -> Result<Option<Self>, GltfError> {
+        let extension = &extensions["KHR_materials_transmission"];
+        if !extension.is_object() {
+            return Ok(None);
+        }
+        let object = format!("{}.extensions.KHR_materials_transmission", object);
+        Ok(Some(Self {
+            factor: extension["transmissionFactor"].as_f32().unwrap_or(0.0),
+            texture: TextureRef::from_gltf(extension, &object, "transmissionTexture")?,
+        }))
+    }
+}
+
+/// KHR_materials_volume, the thickness and absorption of the medium under the surface.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Volume {
+    /// In mesh space, 0 means thin walled
+    pub thickness_factor: f32,
+    /// Thickness in the green channel
+    pub thickness_texture: Option<TextureRef>,
+    /// Distance light travels before `attenuation_color` is reached, infinite for no absorption
+    pub attenuation_distance: f32,
+    pub attenuation_color: [f32; 3],
+}
+
+impl Volume {
+    fn from_gltf(extensions: &JsonValue, object: &str) -> Result<Option<Self>, GltfError> {
+        let extension = &extensions["KHR_materials_volume"];
+        if !extension.is_object() {
+            return Ok(None);
+        }
+        let object = format!("{}.extensions.KHR_materials_volume", object);
+        Ok(Some(Self {
+            thickness_factor: extension["thicknessFactor"].as_f32().unwrap_or(0.0),
+            thickness_texture: TextureRef::from_gltf(extension, &object, "thicknessTexture")?,
+            attenuation_distance: extension["attenuationDistance"].as_f32().unwrap_or(f32::INFINITY),
+            attenuation_color: mesh::optional_f32_array(extension, &object, "attenuationColor")?.unwrap_or([1.0; 3]),
+        }))
+    }
+}
+
+/// KHR_materials_specular, strength and tint of the dielectric specular reflection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Specular {
+    pub factor: f32,
+    /// Strength in the alpha channel
+    pub texture: Option<TextureRef>,
+    pub color_factor: [f32; 3],
+    pub color_texture: Option<TextureRef>,
+}
+
+impl Specular {
+    fn from_gltf(extensions: &JsonValue, object: &str) -> Result<Option<Self>, GltfError> {
+        let extension = &extensions["KHR_materials_specular"];
+        if !extension.is_object() {
+            return Ok(None);
+        }
+        let object = format!("{}.extensions.KHR_materials_specular", object);
+        Ok(Some(Self {
+            factor: extension["specularFactor"].as_f32().unwrap_or(1.0),
+            texture: TextureRef::from_gltf(extension, &object, "specularTexture")?,
+            color_factor: mesh::optional_f32_array(extension, &object, "specularColorFactor")?.unwrap_or([1.0; 3]),
+            color_texture: TextureRef::from_gltf(extension, &object, "specularColorTexture")?,
+        }))
+    }
+}
+
+/// A glTF metallic-roughness material.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Roughness in the green channel, metalness in the blue channel
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<TextureRef>,
+    /// KHR_materials_emissive_strength, scales `emissive_factor` past 1
+    pub emissive_strength: f32,
+    /// KHR_materials_unlit, shaded with the base color only
+    pub unlit: bool,
+    pub clearcoat: Option<Clearcoat>,
+    pub sheen: Option<Sheen>,
+    pub transmission: Option<Transmission>,
+    pub volume: Option<Volume>,
+    pub specular: Option<Specular>,
+    /// KHR_materials_ior, index of refraction of the dielectric part
+    pub ior: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    /// The material the spec mandates for primitives without one.
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            emissive_strength: 1.0,
+            unlit: false,
+            clearcoat: None,
+            sheen: None,
+            transmission: None,
+            volume: None,
+            specular: None,
+            ior: 1.5,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl Material {
+    pub(crate) fn from_gltf(material: &JsonValue, index: usize) -> Result<Self, GltfError> {
+        let object = format!("materials[{}]", index);
+        let pbr = &material["pbrMetallicRoughness"];
+        let pbr_object = format!("{}.pbrMetallicRoughness", object);
+        let extensions = &material["extensions"];
+        let defaults = Self::default();
+
+        let alpha_mode = match material["alphaMode"].as_str() {
+            None | Some("OPAQUE") => AlphaMode::Opaque,
+            Some("MASK") => AlphaMode::Mask,
+            Some("BLEND") => AlphaMode::Blend,
+            Some(_) => return Err(GltfError::MissingProperty { object, property: "alphaMode" }),
+        };
+
+        let normal_texture = TextureRef::from_gltf(material, &object, "normalTexture")?;
+        let occlusion_texture = TextureRef::from_gltf(material, &object, "occlusionTexture")?;
+
+        let material = Self {
+            name: material["name"].as_str().map(String::from),
+            base_color_factor: mesh::optional_f32_array(pbr, &pbr_object, "baseColorFactor")?.unwrap_or(defaults.base_color_factor),
+            base_color_texture: TextureRef::from_gltf(pbr, &pbr_object, "baseColorTexture")?,
+            metallic_factor: pbr["metallicFactor"].as_f32().unwrap_or(defaults.metallic_factor),
+            roughness_factor: pbr["roughnessFactor"].as_f32().unwrap_or(defaults.roughness_factor),
+            metallic_roughness_texture: TextureRef::from_gltf(pbr, &pbr_object, "metallicRoughnessTexture")?,
+            normal_scale: material["normalTexture"]["scale"].as_f32().unwrap_or(defaults.normal_scale),
+            normal_texture,
+            occlusion_strength: material["occlusionTexture"]["strength"].as_f32().unwrap_or(defaults.occlusion_strength),
+            occlusion_texture,
+            emissive_factor: mesh::optional_f32_array(material, &object, "emissiveFactor")?.unwrap_or(defaults.emissive_factor),
+            emissive_texture: TextureRef::from_gltf(material, &object, "emissiveTexture")?,
+            emissive_strength: extensions["KHR_materials_emissive_strength"]["emissiveStrength"].as_f32().unwrap_or(defaults.emissive_strength),
+            unlit: extensions["KHR_materials_unlit"].is_object(),
+            clearcoat: Clearcoat::from_gltf(extensions, &object)?,
+            sheen: Sheen::from_gltf(extensions, &object)?,
+            transmission: Transmission::from_gltf(extensions, &object)?,
+            volume: Volume::from_gltf(extensions, &object)?,
+            specular: Specular::from_gltf(extensions, &object)?,
+            ior: extensions["KHR_materials_ior"]["ior"].as_f32().unwrap_or(defaults.ior),
+            alpha_mode,
+            alpha_cutoff: material["alphaCutoff"].as_f32().unwrap_or(defaults.alpha_cutoff),
+            double_sided: material["doubleSided"].as_bool().unwrap_or(defaults.double_sided),
+        };
+        // Vertices only carry two uv sets, sampling another one with TEXCOORD_0 would silently look wrong
+        if let Some(texture_ref) = material.texture_slots().into_iter().flat_map(|(texture_ref, _)| texture_ref).find(|texture_ref| texture_ref.tex_coord > 1) {
+            return Err(GltfError::Material { index, reason: format!("texture {} uses TEXCOORD_{}, only TEXCOORD_0 and TEXCOORD_1 are supported", texture_ref.texture, texture_ref.tex_coord) });
+        }
+        Ok(material)
+    }
+
+    /// The material object `from_gltf` reads, properties at their default value are left out.
+    /// Every extension the object uses is added to `extensions_used`.
+    pub(crate) fn to_gltf(&self, extensions_used: &mut Vec<String>) -> JsonValue {
+        let defaults = Self::default();
+        let texture = TextureRef::to_gltf;
+        let mut material = JsonValue::new_object();
+        if let Some(name) = &self.name {
+            material["name"] = name.as_str().into();
+        }
+
+        let mut pbr = JsonValue::new_object();
+        if self.base_color_factor != defaults.base_color_factor {
+            pbr["baseColorFactor"] = self.base_color_factor.to_vec().into();
+        }
+        if let Some(texture_ref) = &self.base_color_texture {
+            pbr["baseColorTexture"] = texture(texture_ref);
+        }
+        if self.metallic_factor != defaults.metallic_factor {
+            pbr["metallicFactor"] = self.metallic_factor.into();
+        }
+        if self.roughness_factor != defaults.roughness_factor {
+            pbr["roughnessFactor"] = self.roughness_factor.into();
+        }
+        if let Some(texture_ref) = &self.metallic_roughness_texture {
+            pbr["metallicRoughnessTexture"] = texture(texture_ref);
+        }
+        material["pbrMetallicRoughness"] = pbr;
+
+        if let Some(texture_ref) = &self.normal_texture {
+            material["normalTexture"] = texture(texture_ref);
+            material["normalTexture"]["scale"] = self.normal_scale.into();
+        }
+        if let Some(texture_ref) = &self.occlusion_texture {
+            material["occlusionTexture"] = texture(texture_ref);
+            material["occlusionTexture"]["strength"] = self.occlusion_strength.into();
+        }
+        if self.emissive_factor != defaults.emissive_factor {
+            material["emissiveFactor"] = self.emissive_factor.to_vec().into();
+        }
+        if let Some(texture_ref) = &self.emissive_texture {
+            material["emissiveTexture"] = texture(texture_ref);
+        }
+        match self.alpha_mode {
+            AlphaMode::Opaque => {}
+            AlphaMode::Mask => {
+                material["alphaMode"] = "MASK".into();
+                material["alphaCutoff"] = self.alpha_cutoff.into();
+            }
+            AlphaMode::Blend => material["alphaMode"] = "BLEND".into(),
+        }
+        if self.double_sided {
+            material["doubleSided"] = true.into();
+        }
+
+        let mut extensions = JsonValue::new_object();
+        if self.emissive_strength != defaults.emissive_strength {
+            extensions["KHR_materials_emissive_strength"] = json::object! { "emissiveStrength": self.emissive_strength };
+        }
+        if self.unlit {
+            extensions["KHR_materials_unlit"] = JsonValue::new_object();
+        }
+        if let Some(clearcoat) = &self.clearcoat {
+            let mut extension = json::object! {
+                "clearcoatFactor": clearcoat.factor,
+                "clearcoatRoughnessFactor": clearcoat.roughness_factor,
+            };
+            if let Some(texture_ref) = &clearcoat.texture {
+                extension["clearcoatTexture"] = texture(texture_ref);
+            }
+            if let Some(texture_ref) = &clearcoat.roughness_texture {
+                extension["clearcoatRoughnessTexture"] = texture(texture_ref);
+            }
+            if let Some(texture_ref) = &clearcoat.normal_texture {
+                extension["clearcoatNormalTexture"] = texture(texture_ref);
+                extension["clearcoatNormalTexture"]["scale"] = clearcoat.normal_scale.into();
+            }
+            extensions["KHR_materials_clearcoat"] = extension;
+        }
+        if let Some(sheen) = &self.sheen {
+            let mut extension = json::object! {
+                "sheenColorFactor": sheen.color_factor.to_vec(),
+                "sheenRoughnessFactor": sheen.roughness_factor,
+            };
+            if let Some(texture_ref) = &sheen.color_texture {
+                extension["sheenColorTexture"] = texture(texture_ref);
+            }
+            if let Some(texture_ref) = &sheen.roughness_texture {
+                extension["sheenRoughnessTexture"] = texture(texture_ref);
+            }
+            extensions["KHR_materials_sheen"] = extension;
+        }
+        if let Some(transmission) = &self.transmission {
+            let mut extension = json::object! { "transmissionFactor": transmission.factor };
+            if let Some(texture_ref) = &transmission.texture {
+                extension["transmissionTexture"] = texture(texture_ref);
+            }
+            extensions["KHR_materials_transmission"] = extension;
+        }
+        if let Some(volume) = &self.volume {
+            let mut extension = json::object! {
+                "thicknessFactor": volume.thickness_factor,
+                "attenuationColor": volume.attenuation_color.to_vec(),
+            };
+            // Infinity isn't valid json, leaving the distance out means the same thing
+            if volume.attenuation_distance.is_finite() {
+                extension["attenuationDistance"] = volume.attenuation_distance.into();
+            }
+            if let Some(texture_ref) = &volume.thickness_texture {
+                extension["thicknessTexture"] = texture(texture_ref);
+            }
+            extensions["KHR_materials_volume"] = extension;
+        }
+        if let Some(specular) = &self.specular {
+            let mut extension = json::object! {
+                "specularFactor": specular.factor,
+                "specularColorFactor": specular.color_factor.to_vec(),
+            };
+            if let Some(texture_ref) = &specular.texture {
+                extension["specularTexture"] = texture(texture_ref);
+            }
+            if let Some(texture_ref) = &specular.color_texture {
+                extension["specularColorTexture"] = texture(texture_ref);
+            }
+            extensions["KHR_materials_specular"] = extension;
+        }
+        if self.ior != defaults.ior {
+            extensions["KHR_materials_ior"] = json::object! { "ior": self.ior };
+        }
+        if self.texture_slots().iter().any(|(texture_ref, _)| texture_ref.is_some_and(|texture_ref| texture_ref.transform != TextureTransform::default())) {
+            extensions_used.push("KHR_texture_transform".to_string());
+        }
+        if !extensions.is_empty() {
+            extensions_used.extend(extensions.entries().map(|(extension, _)| extension.to_string()));
+            material["extensions"] = extensions;
+        }
+        material
+    }
+
+    /// The texture of every slot, in `MaterialTextures` order, and whether the slot holds sRGB color.
+    pub fn texture_slots(&self) -> [(Option<&TextureRef>, bool); GpuMaterial::TEXTURE_SLOTS] {
+        let clearcoat = self.clearcoat.as_ref();
+        let sheen = self.sheen.as_ref();
+        let specular = self.specular.as_ref();
+        [
+            (self.base_color_texture.as_ref(), true),
+            (self.metallic_roughness_texture.as_ref(), false),
+            (self.normal_texture.as_ref(), false),
+            (self.occlusion_texture.as_ref(), false),
+            (self.emissive_texture.as_ref(), true),
+            (clearcoat.and_then(|clearcoat| clearcoat.texture.as_ref()), false),
+            (clearcoat.and_then(|clearcoat| clearcoat.roughness_texture.as_ref()), false),
+            (clearcoat.and_then(|clearcoat| clearcoat.normal_texture.as_ref()), false),
+            (sheen.and_then(|sheen| sheen.color_texture.as_ref()), true),
+            (sheen.and_then(|sheen| sheen.roughness_texture.as_ref()), false),
+            (self.transmission.as_ref().and_then(|transmission| transmission.texture.as_ref()), false),
+            (self.volume.as_ref().and_then(|volume| volume.thickness_texture.as_ref()), false),
+            (specular.and_then(|specular| specular.texture.as_ref()), false),
+            (specular.and_then(|specular| specular.color_texture.as_ref()), true),
+        ]
+    }
+
+    /// Whether the material refracts the scene behind it and has to be drawn after the opaque pass.
+    pub fn is_transmissive(&self) -> bool {
+        self.transmission.as_ref().is_some_and(|transmission| transmission.factor > 0.0 || transmission.texture.is_some())
+    }
+
+    /// Loads all materials of a glTF file, in order so submesh material indices refer into the result.
+    pub(crate) fn all_from_gltf(gltf: &mesh::GltfDocument) -> Result<Vec<Self>, GltfError> {
+        gltf.json["materials"].members().enumerate().map(|(i, material)| Self::from_gltf(material, i)).collect()
+    }
+}
+
+/// Material factors as laid out in the shader's material uniform.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    base_color_factor: [f32; 4],
+    /// rgb emissive factor, w unused
+    emissive_factor: [f32; 4],
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    /// Negative when alpha testing is disabled
+    alpha_cutoff: f32,
+    /// 1 for unlit materials
+    unlit: u32,
+    ior: f32,
+    specular_factor: f32,
+    /// rgb specular color, w unused
+    specular_color_factor: [f32; 4],
+    /// rgb sheen color, w unused
+    sheen_color_factor: [f32; 4],
+    /// rgb attenuation color, w unused
+    attenuation_color: [f32; 4],
+    clearcoat_factor: f32,
+    clearcoat_roughness_factor: f32,
+    clearcoat_normal_scale: f32,
+    sheen_roughness_factor: f32,
+    transmission_factor: f32,
+    thickness_factor: f32,
+    attenuation_distance: f32,
+    _padding: f32,
+    /// `TextureTransform::rows` of every texture slot, with the slot's `TextureRef::tex_coord` in w of the first row
+    uv_transforms: [[[f32; 4]; 2]; GpuMaterial::TEXTURE_SLOTS],
+}
+
+impl From<&Material> for MaterialUniform {
+    fn from(material: &Material) -> Self {
+        let extend = |[r, g, b]: [f32; 3]| [r, g, b, 0.0];
+        // Absent extensions fall back to values that leave the base material unchanged
+        let clearcoat = material.clearcoat.as_ref();
+        let sheen = material.sheen.as_ref();
+        let volume = material.volume.as_ref();
+        let specular = material.specular.as_ref();
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: extend(material.emissive_factor.map(|component| component * material.emissive_strength)),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            alpha_cutoff: if material.alpha_mode == AlphaMode::Mask { material.alpha_cutoff } else { -1.0 },
+            unlit: material.unlit as u32,
+            ior: material.ior,
+            specular_factor: specular.map_or(1.0, |specular| specular.factor),
+            specular_color_factor: extend(specular.map_or([1.0; 3], |specular| specular.color_factor)),
+            sheen_color_factor: extend(sheen.map_or([0.0; 3], |sheen| sheen.color_factor)),
+            attenuation_color: extend(volume.map_or([1.0; 3], |volume| volume.attenuation_color)),
+            clearcoat_factor: clearcoat.map_or(0.0, |clearcoat| clearcoat.factor),
+            clearcoat_roughness_factor: clearcoat.map_or(0.0, |clearcoat| clearcoat.roughness_factor),
+            clearcoat_normal_scale: clearcoat.map_or(1.0, |clearcoat| clearcoat.normal_scale),
+            sheen_roughness_factor: sheen.map_or(0.0, |sheen| sheen.roughness_factor),
+            transmission_factor: material.transmission.as_ref().map_or(0.0, |transmission| transmission.factor),
+            thickness_factor: volume.map_or(0.0, |volume| volume.thickness_factor),
+            attenuation_distance: volume.map_or(f32::INFINITY, |volume| volume.attenuation_distance),
+            _padding: 0.0,
+            uv_transforms: material.texture_slots().map(|(texture_ref, _)| match texture_ref {
+                Some(texture_ref) => {
+                    let mut rows = texture_ref.transform.rows();
+                    rows[0][3] = texture_ref.tex_coord as f32;
+                    rows
+                }
+                None => TextureTransform::default().rows(),
+            }),
+        }
+    }
+}
+
+/// Textures bound for each material slot, in `Material::texture_slots` order. Slots the material doesn't use get a neutral default.
+pub struct MaterialTextures<'a>(pub [&'a texture::Texture; GpuMaterial::TEXTURE_SLOTS]);
+
+/// Neutral 1x1 textures for material slots without a texture.
+pub struct DefaultTextures {
+    pub white: texture::Texture,
+    /// Tangent space +Z
+    pub flat_normal: texture::Texture,
+}
+
+impl DefaultTextures {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            white: texture::Texture::from_color(device, queue, [255, 255, 255, 255], "default white texture"),
+            flat_normal: texture::Texture::from_color(device, queue, [128, 128, 255, 255], "default normal texture"),
+        }
+    }
+
+    pub fn material_textures(&self) -> MaterialTextures<'_> {
+        let mut textures = [&self.white; GpuMaterial::TEXTURE_SLOTS];
+        for slot in GpuMaterial::NORMAL_SLOTS {
+            textures[slot] = &self.flat_normal;
+        }
+        MaterialTextures(textures)
+    }
+}
+
+/// The glTF textures used by a set of materials, uploaded once per color space they are sampled in.
+pub struct SceneTextures {
+    textures: HashMap<(usize, bool), texture::Texture>,
+}
+
+impl SceneTextures {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, images: &[image::DynamicImage], image_textures: &[ImageTexture], materials: &[Material]) -> Self {
+        let mut textures = HashMap::new();
+        for material in materials {
+            for (texture_ref, srgb) in material.texture_slots() {
+                let Some(texture_ref) = texture_ref else { continue };
+                textures.entry((texture_ref.texture, srgb)).or_insert_with(|| {
+                    let image_texture = &image_textures[texture_ref.texture];
+                    let label = format!("glTF texture {}", texture_ref.texture);
+                    texture::Texture::from_image(device, queue, &images[image_texture.image], Some(&label), srgb, &image_texture.sampler.descriptor())
+                });
+            }
+        }
+        Self { textures }
+    }
+
+    /// The textures to bind for a material, falling back to `defaults` for empty slots.
+    pub fn material_textures<'a>(&'a self, material: &Material, defaults: &'a DefaultTextures) -> MaterialTextures<'a> {
+        let MaterialTextures(mut textures) = defaults.material_textures();
+        for (slot, (texture_ref, srgb)) in material.texture_slots().into_iter().enumerate() {
+            if let Some(texture) = texture_ref.and_then(|texture_ref| self.textures.get(&(texture_ref.texture, srgb))) {
+                textures[slot] = texture;
+            }
+        }
+        MaterialTextures(textures)
+    }
+}
+
+/// A material uploaded to the GPU, ready to be bound at group 0.
+pub struct GpuMaterial {
+    pub bind_group: wgpu::BindGroup,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+    /// Drawn after the opaque pass so it can sample the scene behind it
+    pub transmissive: bool,
+    #[allow(unused)]
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl GpuMaterial {
+    /// Number of texture slots, each one is a texture binding followed by its sampler
+    pub const TEXTURE_SLOTS: usize = 14;
+    /// Slots holding tangent space normal maps, their default is a flat normal instead of white
+    const NORMAL_SLOTS: [usize; 2] = [2, 7];
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        for slot in 0..Self::TEXTURE_SLOTS as u32 {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1 + slot * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + slot * 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Material bind group layout"),
+            entries: &entries,
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, material: &Material, textures: &MaterialTextures) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material buffer"),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(material)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        for (slot, texture) in textures.0.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 1 + slot as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + slot as u32 * 2,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(material.name.as_deref().unwrap_or("Material bind group")),
+            layout,
+            entries: &entries,
+        });
+
+        Self {
+            bind_group,
+            alpha_mode: material.alpha_mode,
+            double_sided: material.double_sided,
+            transmissive: material.is_transmissive(),
+            uniform_buffer,
+        }
+    }
+}
@@ -1,13 +1,39 @@
+use base64::Engine;
 use cgmath::EuclideanSpace;
 use json::JsonValue;
-use std::{fs, collections::HashMap};
+use std::fs;
+use wgpu::util::DeviceExt;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: [f32; 3],
-    pub color: [f32; 3],
+    pub color: [f32; 4],
     pub uv: [f32; 2],
+    pub normal: [f32; 3],
+    /// xyz is the tangent, w is the handedness of the bitangent (cross(normal, tangent) * w)
+    pub tangent: [f32; 4],
+    /// Indices into the joints of the skin the mesh is drawn with
+    pub joints: [u32; 4],
+    /// How much each of `joints` moves the vertex, all zero for vertices that aren't skinned
+    pub weights: [f32; 4],
+    /// Second uv set, TEXCOORD_1, which occlusion maps and lightmaps often use
+    pub uv1: [f32; 2],
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+            uv: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            joints: [0, 0, 0, 0],
+            weights: [0.0, 0.0, 0.0, 0.0],
+            uv1: [0.0, 0.0],
+        }
+    }
 }
 
 impl Vertex {
@@ -24,21 +50,271 @@ impl Vertex {
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
                 wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3+3]>() as wgpu::BufferAddress, // Note that we offset 3 + 3 here
+                    offset: std::mem::size_of::<[f32; 3+4]>() as wgpu::BufferAddress, // Note that we offset 3 + 4 here
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x2,
-                }
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3+4+2]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3+4+2+3]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3+4+2+3+4]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3+4+2+3+4+4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3+4+2+3+4+4+4]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
 }
 
+/// Splits a polygon into triangles fanning out from its first corner, which assumes it is convex.
+pub(crate) fn triangulate_fan(polygon: &[u32], indices: &mut Vec<u32>) {
+    for i in 1..polygon.len().saturating_sub(1) {
+        indices.extend([polygon[0], polygon[i], polygon[i + 1]]);
+    }
+}
+
+/// Moves a uv from the bottom left origin of OBJ and PLY to the top left origin of glTF and wgpu.
+pub(crate) fn uv_from_bottom_left([u, v]: [f32; 2]) -> [f32; 2] {
+    [u, 1.0 - v]
+}
+
+/// Splits every triangle into its own vertices and gives them the face normal, which is what the
+/// glTF spec asks for when a primitive has no normals.
+pub(crate) fn flat_shaded(verts: &[Vertex], indices: &Indices) -> Vec<Vertex> {
+    use cgmath::InnerSpace;
+    let mut flat_verts = Vec::with_capacity(indices.len());
+    for triangle in 0..indices.len() / 3 {
+        let mut corners = [0, 1, 2].map(|corner| verts[indices.get(triangle * 3 + corner) as usize]);
+        let [p0, p1, p2] = corners.map(|vertex| cgmath::Vector3::from(vertex.position));
+        let normal = (p1 - p0).cross(p2 - p0);
+        // Degenerate triangles get an arbitrary normal rather than NaNs
+        let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { cgmath::vec3(0.0, 0.0, 1.0) };
+        for corner in &mut corners {
+            corner.normal = normal.into();
+        }
+        flat_verts.extend(corners);
+    }
+    flat_verts
+}
+
+/// Generates per-vertex tangents from the uv layout, accumulated over every triangle sharing a vertex.
+pub(crate) fn generate_tangents(verts: &mut [Vertex], indices: &Indices) {
+    use cgmath::InnerSpace;
+    let mut tangents = vec![cgmath::vec3(0.0f32, 0.0, 0.0); verts.len()];
+    let mut bitangents = vec![cgmath::vec3(0.0f32, 0.0, 0.0); verts.len()];
+    for triangle in 0..indices.len() / 3 {
+        let corners = [0, 1, 2].map(|corner| indices.get(triangle * 3 + corner) as usize);
+        let [p0, p1, p2] = corners.map(|i| cgmath::Vector3::from(verts[i].position));
+        let [uv0, uv1, uv2] = corners.map(|i| cgmath::Vector2::from(verts[i].uv));
+        let (edge1, edge2) = (p1 - p0, p2 - p0);
+        let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+        let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) * r;
+        // glTF uvs have v pointing down while the bitangent points up in texture space, hence the flipped sign
+        let bitangent = (edge1 * duv2.x - edge2 * duv1.x) * r;
+        for i in corners {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in verts.iter_mut().enumerate() {
+        let normal = cgmath::Vector3::from(vertex.normal);
+        // Gram-Schmidt orthogonalize against the normal
+        let tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < f32::EPSILON {
+            continue;
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
+
+/// Index data in the width it was authored in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U8(indices) => indices.len(),
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            Indices::U8(indices) => indices[i] as u32,
+            Indices::U16(indices) => indices[i] as u32,
+            Indices::U32(indices) => indices[i],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// 0, 1, 2, ... for `count` vertices, in the narrowest type that fits.
+    pub fn sequential(count: usize) -> Self {
+        if count <= u8::MAX as usize + 1 {
+            Indices::U8((0..count).map(|i| i as u8).collect())
+        } else if count <= u16::MAX as usize + 1 {
+            Indices::U16((0..count).map(|i| i as u16).collect())
+        } else {
+            Indices::U32((0..count as u32).collect())
+        }
+    }
+
+    /// 16-bit indices when every index fits, 32-bit otherwise.
+    pub fn from_u32(indices: Vec<u32>) -> Self {
+        if indices.iter().all(|index| *index <= u16::MAX as u32) {
+            Indices::U16(indices.into_iter().map(|index| index as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    /// Appends `other`, widening the stored indices if `other` uses a wider type.
+    pub fn append(&mut self, other: Indices) {
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+        match (&mut *self, other) {
+            (Indices::U8(indices), Indices::U8(other)) => indices.extend(other),
+            (Indices::U16(indices), Indices::U8(other)) => indices.extend(other.into_iter().map(u16::from)),
+            (Indices::U16(indices), Indices::U16(other)) => indices.extend(other),
+            (Indices::U32(indices), other) => indices.extend(other.iter()),
+            (_, Indices::U16(other)) => {
+                let mut widened = self.iter().map(|i| i as u16).collect::<Vec<_>>();
+                widened.extend(other);
+                *self = Indices::U16(widened);
+            }
+            (_, Indices::U32(other)) => {
+                let mut widened = self.iter().collect::<Vec<_>>();
+                widened.extend(other);
+                *self = Indices::U32(widened);
+            }
+        }
+    }
+
+    /// The format used on the GPU, WebGPU has no 8-bit index format so those are uploaded as 16-bit.
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        match self {
+            Indices::U8(_) | Indices::U16(_) => wgpu::IndexFormat::Uint16,
+            Indices::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    /// Index buffer contents matching `index_format`.
+    pub fn gpu_data(&self) -> std::borrow::Cow<'_, [u8]> {
+        match self {
+            Indices::U8(indices) => std::borrow::Cow::Owned(indices.iter().flat_map(|i| u16::from(*i).to_le_bytes()).collect()),
+            Indices::U16(indices) => std::borrow::Cow::Borrowed(bytemuck::cast_slice(indices)),
+            Indices::U32(indices) => std::borrow::Cow::Borrowed(bytemuck::cast_slice(indices)),
+        }
+    }
+}
+
+/// How the indices of a submesh are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// Every three indices are a triangle
+    Triangles,
+    /// Every index is a point, drawn as a splat
+    Points,
+}
+
+/// A draw range within a `Mesh`, one per glTF primitive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submesh {
+    /// Range of `Mesh::indices` drawn by this submesh
+    pub index_range: std::ops::Range<u32>,
+    /// Triangles are drawn with the submesh's material, points as splats without one
+    pub topology: Topology,
+    /// Added to every index of this submesh, the indices are relative to the submesh's first vertex
+    pub base_vertex: i32,
+    /// Index into the glTF materials, None uses the default material
+    pub material: Option<usize>,
+    /// KHR_materials_variants, (variant, material) pairs that replace `material` while the variant is active
+    pub variant_materials: Vec<(usize, usize)>,
+}
+
+impl Submesh {
+    /// The material to draw with while `variant` is active, variants without a mapping keep the default material.
+    pub fn material_for_variant(&self, variant: Option<usize>) -> Option<usize> {
+        variant
+            .and_then(|variant| self.variant_materials.iter().find(|(mapped_variant, _)| *mapped_variant == variant))
+            .map_or(self.material, |(_, material)| Some(*material))
+    }
+}
+
 pub struct Mesh {
     pub verts: Vec<Vertex>,
-    pub indices: Vec<u16>,
+    pub indices: Indices,
+    pub submeshes: Vec<Submesh>,
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+/// Offsets added to every vertex of a mesh, scaled by the target's weight.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTarget {
+    /// One per vertex of the mesh, zero where the file has no delta
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Tangent xyz, morph targets don't change the handedness
+    pub tangents: Vec<[f32; 3]>,
+}
+
+impl MorphTarget {
+    fn zeroed(vertex_count: usize) -> Self {
+        Self {
+            positions: vec![[0.0; 3]; vertex_count],
+            normals: vec![[0.0; 3]; vertex_count],
+            tangents: vec![[0.0; 3]; vertex_count],
+        }
+    }
+
+    fn extend(&mut self, other: MorphTarget) {
+        self.positions.extend(other.positions);
+        self.normals.extend(other.normals);
+        self.tangents.extend(other.tangents);
+    }
 }
 
 fn f32_from_buffer_slice(offset: usize, slice: &[u8]) -> f32 {
@@ -47,126 +323,867 @@ fn f32_from_buffer_slice(offset: usize, slice: &[u8]) -> f32 {
     f32::from_le_bytes(f32_data)
 }
 
-fn get_attributes_buffer_slice<'a>(buffers: &'a Vec<Vec<u8>>, name: &str, attributes: &JsonValue, accessors: &Vec<&JsonValue>, buffer_views: &Vec<&JsonValue>) -> &'a [u8] {
-    let position_attribute_value = &attributes[name];
-    assert!(*position_attribute_value != json::Null);
-    let position_accessor_index = position_attribute_value.as_usize().unwrap();
-    // Only handle positions being floats for now.
-    assert!(accessors[position_accessor_index]["componentType"] == 5126);
-    let position_buffer_view = buffer_views[accessors[position_accessor_index]["bufferView"].as_usize().unwrap()];
-    let position_buffer_offset = position_buffer_view["byteOffset"].as_usize().unwrap();
-    let position_buffer_length = position_buffer_view["byteLength"].as_usize().unwrap();
-    &buffers[position_buffer_view["buffer"].as_usize().unwrap()][position_buffer_offset..position_buffer_offset+position_buffer_length]
+fn u32_from_buffer_slice(offset: usize, slice: &[u8]) -> u32 {
+    let mut u32_data: [u8; 4] = [0; 4];
+    u32_data.copy_from_slice(&slice[offset..=offset+3]);
+    u32::from_le_bytes(u32_data)
 }
 
-impl Mesh {
-    pub fn load_gltf (path: &std::path::Path) -> Self {
-        // Read and parse json
-        let gltf = fs::read_to_string(path).unwrap();
-        let gltf_json = json::parse(&gltf).unwrap();
+// Binary glTF container, see https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#binary-gltf-layout
+pub(crate) const GLB_MAGIC: u32 = 0x46546C67; // "glTF"
+pub(crate) const GLB_HEADER_LENGTH: usize = 12;
+pub(crate) const GLB_CHUNK_HEADER_LENGTH: usize = 8;
+pub(crate) const GLB_CHUNK_TYPE_JSON: u32 = 0x4E4F534A; // "JSON"
+pub(crate) const GLB_CHUNK_TYPE_BIN: u32 = 0x004E4942; // "BIN\0"
+
+/// Extensions the loader implements, files that require any other extension are rejected.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "KHR_texture_transform",
+    "KHR_materials_unlit",
+    "KHR_materials_emissive_strength",
+    "KHR_materials_clearcoat",
+    "KHR_materials_sheen",
+    "KHR_materials_transmission",
+    "KHR_materials_volume",
+    "KHR_materials_specular",
+    "KHR_materials_ior",
+    "KHR_materials_variants",
+    "EXT_mesh_gpu_instancing",
+    "KHR_lights_punctual",
+];
+
+/// Why loading a glTF file failed, naming the part of the file that was at fault.
+#[derive(Debug)]
+pub enum GltfError {
+    /// A file could not be read, either the glTF itself or a file it references
+    Io { path: std::path::PathBuf, source: std::io::Error },
+    /// The file is not valid json
+    Json(json::Error),
+    /// The .glb container is malformed
+    Glb(String),
+    /// Only glTF 2.0 is supported
+    UnsupportedVersion(String),
+    /// The file lists an extension in extensionsRequired that isn't in `SUPPORTED_EXTENSIONS`
+    UnsupportedExtension(String),
+    /// A uri could not be resolved or decoded
+    Uri { uri: String, reason: String },
+    /// A required property is missing or has the wrong type, `object` is e.g. "accessors[3]"
+    MissingProperty { object: String, property: &'static str },
+    Buffer { index: usize, reason: String },
+    BufferView { index: usize, reason: String },
+    Accessor { index: usize, reason: String },
+    Mesh { index: usize, reason: String },
+    Node { index: usize, reason: String },
+    Scene { index: usize, reason: String },
+    Material { index: usize, reason: String },
+    Texture { index: usize, reason: String },
+    Image { index: usize, reason: String },
+    Sampler { index: usize, reason: String },
+    Skin { index: usize, reason: String },
+    Animation { index: usize, reason: String },
+}
+
+impl std::fmt::Display for GltfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GltfError::Io { path, source } => write!(f, "failed to read {}: {}", path.display(), source),
+            GltfError::Json(error) => write!(f, "invalid json: {}", error),
+            GltfError::Glb(reason) => write!(f, "invalid glb container: {}", reason),
+            GltfError::UnsupportedVersion(version) => write!(f, "unsupported glTF version {}, only 2.0 is supported", version),
+            GltfError::UnsupportedExtension(extension) => write!(f, "required extension {} is not supported", extension),
+            GltfError::Uri { uri, reason } => {
+                // Data uris can be megabytes long, only show what kind of uri it was
+                let uri = if uri.starts_with("data:") { "data:..." } else { uri.as_str() };
+                write!(f, "uri {}: {}", uri, reason)
+            }
+            GltfError::MissingProperty { object, property } => write!(f, "{} is missing required property {} or it has the wrong type", object, property),
+            GltfError::Buffer { index, reason } => write!(f, "buffers[{}]: {}", index, reason),
+            GltfError::BufferView { index, reason } => write!(f, "bufferViews[{}]: {}", index, reason),
+            GltfError::Accessor { index, reason } => write!(f, "accessors[{}]: {}", index, reason),
+            GltfError::Mesh { index, reason } => write!(f, "meshes[{}]: {}", index, reason),
+            GltfError::Node { index, reason } => write!(f, "nodes[{}]: {}", index, reason),
+            GltfError::Scene { index, reason } => write!(f, "scenes[{}]: {}", index, reason),
+            GltfError::Material { index, reason } => write!(f, "materials[{}]: {}", index, reason),
+            GltfError::Texture { index, reason } => write!(f, "textures[{}]: {}", index, reason),
+            GltfError::Image { index, reason } => write!(f, "images[{}]: {}", index, reason),
+            GltfError::Sampler { index, reason } => write!(f, "samplers[{}]: {}", index, reason),
+            GltfError::Skin { index, reason } => write!(f, "skins[{}]: {}", index, reason),
+            GltfError::Animation { index, reason } => write!(f, "animations[{}]: {}", index, reason),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Io { source, .. } => Some(source),
+            GltfError::Json(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a required unsigned integer property of a glTF object, `object` names it in the error.
+pub(crate) fn required_usize(value: &JsonValue, object: &str, property: &'static str) -> Result<usize, GltfError> {
+    value[property].as_usize().ok_or_else(|| GltfError::MissingProperty { object: object.to_string(), property })
+}
+
+/// Reads a required string property of a glTF object, `object` names it in the error.
+pub(crate) fn required_str<'a>(value: &'a JsonValue, object: &str, property: &'static str) -> Result<&'a str, GltfError> {
+    value[property].as_str().ok_or_else(|| GltfError::MissingProperty { object: object.to_string(), property })
+}
+
+/// Reads an optional fixed size number array property such as a translation or a color factor.
+pub(crate) fn optional_f32_array<const N: usize>(value: &JsonValue, object: &str, property: &'static str) -> Result<Option<[f32; N]>, GltfError> {
+    let array = &value[property];
+    if array.is_null() {
+        return Ok(None);
+    }
+    let mut result = [0.0; N];
+    if array.len() != N {
+        return Err(GltfError::MissingProperty { object: object.to_string(), property });
+    }
+    for (element, member) in result.iter_mut().zip(array.members()) {
+        *element = member.as_f32().ok_or_else(|| GltfError::MissingProperty { object: object.to_string(), property })?;
+    }
+    Ok(Some(result))
+}
+
+/// Splits a .glb file into its JSON chunk and the optional BIN chunk.
+fn parse_glb(data: &[u8]) -> Result<(&str, Option<&[u8]>), GltfError> {
+    if data.len() < GLB_HEADER_LENGTH {
+        return Err(GltfError::Glb(format!("file is {} bytes, shorter than the header", data.len())));
+    }
+    if u32_from_buffer_slice(0, data) != GLB_MAGIC {
+        return Err(GltfError::Glb("wrong magic".to_string()));
+    }
+    let version = u32_from_buffer_slice(4, data);
+    if version != 2 {
+        return Err(GltfError::UnsupportedVersion(version.to_string()));
+    }
+    // The header length covers the whole file, anything after it is not ours to read
+    let length = u32_from_buffer_slice(8, data) as usize;
+    if length > data.len() {
+        return Err(GltfError::Glb(format!("header length {} is larger than the file ({} bytes)", length, data.len())));
+    }
+    let data = &data[..length];
+
+    let mut json_chunk = None;
+    let mut bin_chunk = None;
+    let mut offset = GLB_HEADER_LENGTH;
+    while offset + GLB_CHUNK_HEADER_LENGTH <= data.len() {
+        let chunk_length = u32_from_buffer_slice(offset, data) as usize;
+        let chunk_type = u32_from_buffer_slice(offset + 4, data);
+        let chunk_start = offset + GLB_CHUNK_HEADER_LENGTH;
+        if chunk_start + chunk_length > data.len() {
+            return Err(GltfError::Glb(format!("chunk at byte {} with length {} runs past the end of the file", offset, chunk_length)));
+        }
+        let chunk = &data[chunk_start..chunk_start + chunk_length];
+        match chunk_type {
+            // The JSON chunk always comes first and there is at most one BIN chunk right after it
+            GLB_CHUNK_TYPE_JSON if json_chunk.is_none() => json_chunk = Some(chunk),
+            GLB_CHUNK_TYPE_BIN if json_chunk.is_some() && bin_chunk.is_none() => bin_chunk = Some(chunk),
+            // Unknown chunks must be ignored
+            _ => (),
+        }
+        // Chunks are padded to 4 byte alignment
+        offset = chunk_start + ((chunk_length + 3) & !3);
+    }
+
+    // The JSON chunk is padded with trailing spaces which the json parser is fine with
+    let json_chunk = json_chunk.ok_or_else(|| GltfError::Glb("missing JSON chunk".to_string()))?;
+    let json_chunk = std::str::from_utf8(json_chunk).map_err(|_| GltfError::Glb("JSON chunk is not valid utf-8".to_string()))?;
+    Ok((json_chunk, bin_chunk))
+}
+
+// glTF componentType values
+pub(crate) const COMPONENT_TYPE_BYTE: u32 = 5120;
+pub(crate) const COMPONENT_TYPE_UNSIGNED_BYTE: u32 = 5121;
+pub(crate) const COMPONENT_TYPE_SHORT: u32 = 5122;
+pub(crate) const COMPONENT_TYPE_UNSIGNED_SHORT: u32 = 5123;
+pub(crate) const COMPONENT_TYPE_UNSIGNED_INT: u32 = 5125;
+pub(crate) const COMPONENT_TYPE_FLOAT: u32 = 5126;
+
+// glTF primitive mode values, only points (drawn as splats) and triangle lists are supported
+pub(crate) const PRIMITIVE_MODE_POINTS: usize = 0;
+pub(crate) const PRIMITIVE_MODE_TRIANGLES: usize = 4;
+
+/// Byte size of a single component of the given componentType.
+fn component_size(component_type: u32) -> Option<usize> {
+    match component_type {
+        COMPONENT_TYPE_BYTE | COMPONENT_TYPE_UNSIGNED_BYTE => Some(1),
+        COMPONENT_TYPE_SHORT | COMPONENT_TYPE_UNSIGNED_SHORT => Some(2),
+        COMPONENT_TYPE_UNSIGNED_INT | COMPONENT_TYPE_FLOAT => Some(4),
+        _ => None,
+    }
+}
+
+/// Number of (columns, rows) in an element of the given accessor type, vectors and scalars are a single column.
+fn element_dimensions(accessor_type: &str) -> Option<(usize, usize)> {
+    match accessor_type {
+        "SCALAR" => Some((1, 1)),
+        "VEC2" => Some((1, 2)),
+        "VEC3" => Some((1, 3)),
+        "VEC4" => Some((1, 4)),
+        "MAT2" => Some((2, 2)),
+        "MAT3" => Some((3, 3)),
+        "MAT4" => Some((4, 4)),
+        _ => None,
+    }
+}
+
+/// Decodes a single component to f32, mapping normalized integers to [0, 1] or [-1, 1] as the spec describes.
+fn component_to_f32(component_type: u32, normalized: bool, bytes: &[u8]) -> f32 {
+    match (component_type, normalized) {
+        (COMPONENT_TYPE_FLOAT, _) => f32_from_buffer_slice(0, bytes),
+        (COMPONENT_TYPE_BYTE, false) => bytes[0] as i8 as f32,
+        (COMPONENT_TYPE_BYTE, true) => (bytes[0] as i8 as f32 / 127.0).max(-1.0),
+        (COMPONENT_TYPE_UNSIGNED_BYTE, false) => bytes[0] as f32,
+        (COMPONENT_TYPE_UNSIGNED_BYTE, true) => bytes[0] as f32 / 255.0,
+        (COMPONENT_TYPE_SHORT, false) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+        (COMPONENT_TYPE_SHORT, true) => (i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32767.0).max(-1.0),
+        (COMPONENT_TYPE_UNSIGNED_SHORT, false) => u16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+        (COMPONENT_TYPE_UNSIGNED_SHORT, true) => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 65535.0,
+        (COMPONENT_TYPE_UNSIGNED_INT, false) => u32_from_buffer_slice(0, bytes) as f32,
+        // Not allowed by the spec, but there is only one sensible way to read it
+        (COMPONENT_TYPE_UNSIGNED_INT, true) => (u32_from_buffer_slice(0, bytes) as f64 / u32::MAX as f64) as f32,
+        // AccessorView validates the componentType
+        _ => unreachable!("invalid accessor componentType {}", component_type),
+    }
+}
+
+/// Largest accessor without a bufferView that gets materialized, 256 MiB of zeroes is far beyond any real file.
+const MAX_ZEROED_ACCESSOR_LENGTH: usize = 256 << 20;
+
+/// The elements of an accessor as they are laid out in its bufferView, taking the accessor byteOffset
+/// and the bufferView byteStride into account so interleaved and packed bufferViews work.
+/// Sparse accessors and accessors without a bufferView are materialized into tightly packed elements instead.
+struct AccessorView<'a> {
+    index: usize,
+    /// Element data starting at the first element of the accessor
+    data: std::borrow::Cow<'a, [u8]>,
+    count: usize,
+    stride: usize,
+    component_type: u32,
+    component_size: usize,
+    normalized: bool,
+    columns: usize,
+    rows: usize,
+}
+
+impl<'a> AccessorView<'a> {
+    fn new(gltf: &'a GltfDocument, accessor_index: usize) -> Result<Self, GltfError> {
+        let accessor = gltf.accessor(accessor_index)?;
+        let object = format!("accessors[{}]", accessor_index);
+        let accessor_error = |reason: String| GltfError::Accessor { index: accessor_index, reason };
+
+        let count = required_usize(accessor, &object, "count")?;
+        let component_type = accessor["componentType"].as_u32().ok_or_else(|| GltfError::MissingProperty { object: object.clone(), property: "componentType" })?;
+        let component_size = component_size(component_type).ok_or_else(|| accessor_error(format!("invalid componentType {}", component_type)))?;
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+        let accessor_type = required_str(accessor, &object, "type")?;
+        let (columns, rows) = element_dimensions(accessor_type).ok_or_else(|| accessor_error(format!("invalid type {}", accessor_type)))?;
+
+        let mut view = Self {
+            index: accessor_index,
+            data: std::borrow::Cow::Borrowed(&[]),
+            count,
+            stride: 0,
+            component_type,
+            component_size,
+            normalized,
+            columns,
+            rows,
+        };
+        let element_size = view.element_size();
+
+        match accessor["bufferView"].as_usize() {
+            Some(buffer_view_index) => {
+                let buffer_view_data = gltf.buffer_view_data(buffer_view_index)?;
+                let buffer_view = gltf.buffer_view(buffer_view_index)?;
+
+                // Without a byteStride the elements are tightly packed
+                view.stride = buffer_view["byteStride"].as_usize().unwrap_or(element_size);
+                if view.stride < element_size {
+                    return Err(GltfError::BufferView { index: buffer_view_index, reason: format!("byteStride {} is smaller than the {} byte elements of accessor {}", view.stride, element_size, accessor_index) });
+                }
+
+                let accessor_offset = accessor["byteOffset"].as_usize().unwrap_or(0);
+                // The last element doesn't need a full stride, only its own size. None when the json sizes overflow.
+                let accessor_length = match count {
+                    0 => Some(0),
+                    _ => view.stride.checked_mul(count - 1).and_then(|length| length.checked_add(element_size)),
+                };
+                let accessor_end = accessor_length.and_then(|length| length.checked_add(accessor_offset));
+                if accessor_end.is_none_or(|end| end > buffer_view_data.len()) {
+                    return Err(accessor_error(format!("byteOffset {} and {} elements with a {} byte stride run past the end of bufferView {} ({} bytes)", accessor_offset, count, view.stride, buffer_view_index, buffer_view_data.len())));
+                }
+                view.data = std::borrow::Cow::Borrowed(&buffer_view_data[accessor_offset..]);
+            }
+            // Without a bufferView every element is zero, usually with sparse values on top
+            None => {
+                // Nothing in the file backs these elements, so a hostile count could ask for any amount of memory
+                let length = element_size.checked_mul(count).filter(|length| *length <= MAX_ZEROED_ACCESSOR_LENGTH)
+                    .ok_or_else(|| accessor_error(format!("{} elements without a bufferView exceed the {} byte limit", count, MAX_ZEROED_ACCESSOR_LENGTH)))?;
+                view.stride = element_size;
+                view.data = std::borrow::Cow::Owned(vec![0; length]);
+            }
+        }
+
+        let sparse = &accessor["sparse"];
+        if !sparse.is_null() {
+            view.apply_sparse(gltf, sparse, &object)?;
+        }
+        Ok(view)
+    }
+
+    /// Writes the sparse values over the elements they replace, after packing the elements tightly.
+    fn apply_sparse(&mut self, gltf: &GltfDocument, sparse: &JsonValue, object: &str) -> Result<(), GltfError> {
+        let sparse_error = |reason: String| GltfError::Accessor { index: self.index, reason: format!("sparse {}", reason) };
+        let sparse_object = format!("{}.sparse", object);
+        let sparse_count = required_usize(sparse, &sparse_object, "count")?;
+        let element_size = self.element_size();
+
+        let indices = &sparse["indices"];
+        let indices_object = format!("{}.indices", sparse_object);
+        let indices_data = gltf.buffer_view_data(required_usize(indices, &indices_object, "bufferView")?)?;
+        let indices_offset = indices["byteOffset"].as_usize().unwrap_or(0);
+        let index_type = indices["componentType"].as_u32().ok_or_else(|| GltfError::MissingProperty { object: indices_object.clone(), property: "componentType" })?;
+        let index_size = match index_type {
+            COMPONENT_TYPE_UNSIGNED_BYTE | COMPONENT_TYPE_UNSIGNED_SHORT | COMPONENT_TYPE_UNSIGNED_INT => component_size(index_type).unwrap_or(1),
+            _ => return Err(sparse_error(format!("indices have invalid componentType {}", index_type))),
+        };
+        let indices_end = sparse_count.checked_mul(index_size).and_then(|length| length.checked_add(indices_offset));
+        if indices_end.is_none_or(|end| end > indices_data.len()) {
+            return Err(sparse_error(format!("byteOffset {} and {} indices run past the end of their bufferView ({} bytes)", indices_offset, sparse_count, indices_data.len())));
+        }
+
+        let values = &sparse["values"];
+        let values_object = format!("{}.values", sparse_object);
+        let values_data = gltf.buffer_view_data(required_usize(values, &values_object, "bufferView")?)?;
+        let values_offset = values["byteOffset"].as_usize().unwrap_or(0);
+        let values_end = sparse_count.checked_mul(element_size).and_then(|length| length.checked_add(values_offset));
+        if values_end.is_none_or(|end| end > values_data.len()) {
+            return Err(sparse_error(format!("byteOffset {} and {} values run past the end of their bufferView ({} bytes)", values_offset, sparse_count, values_data.len())));
+        }
+
+        // Copy out the base elements so the values can be written over them, dropping any byteStride
+        if let std::borrow::Cow::Borrowed(_) = self.data {
+            let mut packed = vec![0; element_size * self.count];
+            for (i, element) in packed.chunks_exact_mut(element_size).enumerate() {
+                element.copy_from_slice(&self.data[i * self.stride..i * self.stride + element_size]);
+            }
+            self.data = std::borrow::Cow::Owned(packed);
+            self.stride = element_size;
+        }
+
+        let data = self.data.to_mut();
+        let mut previous_index = None;
+        for s in 0..sparse_count {
+            let index_bytes = &indices_data[indices_offset + s * index_size..indices_offset + (s + 1) * index_size];
+            let index = match index_size {
+                1 => index_bytes[0] as usize,
+                2 => u16::from_le_bytes([index_bytes[0], index_bytes[1]]) as usize,
+                _ => u32_from_buffer_slice(0, index_bytes) as usize,
+            };
+            if index >= self.count {
+                return Err(sparse_error(format!("index {} is out of range for {} elements", index, self.count)));
+            }
+            if previous_index.is_some_and(|previous| previous >= index) {
+                return Err(sparse_error("indices must be strictly increasing".to_string()));
+            }
+            previous_index = Some(index);
+            let value = &values_data[values_offset + s * element_size..values_offset + (s + 1) * element_size];
+            data[index * element_size..(index + 1) * element_size].copy_from_slice(value);
+        }
+        Ok(())
+    }
+
+    fn component_count(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Matrix columns start on 4 byte boundaries, which pads MAT2/MAT3 of bytes and MAT3 of shorts.
+    fn column_stride(&self) -> usize {
+        (self.rows * self.component_size + 3) & !3
+    }
+
+    fn element_size(&self) -> usize {
+        (self.columns - 1) * self.column_stride() + self.rows * self.component_size
+    }
+
+    /// Raw bytes of component `c` of element `i`.
+    fn component(&self, i: usize, c: usize) -> &[u8] {
+        let start = i * self.stride + (c / self.rows) * self.column_stride() + (c % self.rows) * self.component_size;
+        &self.data[start..start + self.component_size]
+    }
+}
+
+/// Reads every element of an accessor with `N` components, converting any componentType to f32.
+pub(crate) fn read_accessor_f32<const N: usize>(gltf: &GltfDocument, accessor_index: usize) -> Result<Vec<[f32; N]>, GltfError> {
+    let view = AccessorView::new(gltf, accessor_index)?;
+    if view.component_count() != N {
+        return Err(GltfError::Accessor { index: view.index, reason: format!("expected {} components but it has {}", N, view.component_count()) });
+    }
+    Ok((0..view.count).map(|i| {
+        let mut value = [0.0; N];
+        for (c, component) in value.iter_mut().enumerate() {
+            *component = component_to_f32(view.component_type, view.normalized, view.component(i, c));
+        }
+        value
+    }).collect())
+}
+
+/// Reads an index accessor, keeping the width it was stored with.
+fn read_accessor_indices(gltf: &GltfDocument, accessor_index: usize) -> Result<Indices, GltfError> {
+    let view = AccessorView::new(gltf, accessor_index)?;
+    if view.component_count() != 1 {
+        return Err(GltfError::Accessor { index: view.index, reason: "indices must be SCALAR".to_string() });
+    }
+    match view.component_type {
+        COMPONENT_TYPE_UNSIGNED_BYTE => Ok(Indices::U8((0..view.count).map(|i| view.component(i, 0)[0]).collect())),
+        COMPONENT_TYPE_UNSIGNED_SHORT => Ok(Indices::U16((0..view.count).map(|i| {
+            let bytes = view.component(i, 0);
+            u16::from_le_bytes([bytes[0], bytes[1]])
+        }).collect())),
+        COMPONENT_TYPE_UNSIGNED_INT => Ok(Indices::U32((0..view.count).map(|i| u32_from_buffer_slice(0, view.component(i, 0))).collect())),
+        _ => Err(GltfError::Accessor { index: view.index, reason: format!("invalid index componentType {}", view.component_type) }),
+    }
+}
+
+/// Reads the vertex attribute `name` of a primitive, or None if the primitive doesn't have it.
+fn read_attribute<const N: usize>(gltf: &GltfDocument, name: &str, attributes: &JsonValue) -> Result<Option<Vec<[f32; N]>>, GltfError> {
+    match attributes[name].as_usize() {
+        Some(accessor_index) => Ok(Some(read_accessor_f32(gltf, accessor_index)?)),
+        None => Ok(None),
+    }
+}
+
+/// Resolves a glTF uri, either an embedded base64 data uri or a path relative to the glTF file.
+pub(crate) fn read_uri(gltf_path: &std::path::Path, uri: &str) -> Result<Vec<u8>, GltfError> {
+    let uri_error = |reason: &str| GltfError::Uri { uri: uri.to_string(), reason: reason.to_string() };
+    match uri.strip_prefix("data:") {
+        Some(data_uri) => {
+            // data:[<mediatype>][;base64],<data>
+            let (media_type, data) = data_uri.split_once(',').ok_or_else(|| uri_error("data uri without data"))?;
+            // Only base64 is used by exporters in practice
+            if !media_type.ends_with(";base64") {
+                return Err(uri_error("only base64 data uris are supported"));
+            }
+            base64::engine::general_purpose::STANDARD.decode(data).map_err(|error| uri_error(&error.to_string()))
+        }
+        None => {
+            let path = gltf_path.parent().unwrap_or(std::path::Path::new("")).join(uri);
+            fs::read(&path).map_err(|source| GltfError::Io { path, source })
+        }
+    }
+}
+
+/// A parsed glTF file with all of its buffers loaded.
+pub(crate) struct GltfDocument {
+    /// Where the file was loaded from, relative uris are resolved against it
+    pub path: std::path::PathBuf,
+    pub json: JsonValue,
+    pub buffers: Vec<Vec<u8>>,
+}
+
+impl GltfDocument {
+    pub fn open(path: &std::path::Path) -> Result<Self, GltfError> {
+        // Read and parse json, either straight from a .gltf file or from the JSON chunk of a .glb
+        let file_data = fs::read(path).map_err(|source| GltfError::Io { path: path.to_path_buf(), source })?;
+        let (gltf, bin_chunk) = if file_data.len() >= 4 && u32_from_buffer_slice(0, &file_data) == GLB_MAGIC {
+            parse_glb(&file_data)?
+        } else {
+            // json::parse reports invalid utf-8 the same way as any other bad input
+            (std::str::from_utf8(&file_data).unwrap_or(""), None)
+        };
+        let json = json::parse(gltf).map_err(GltfError::Json)?;
 
         // Check so it's glTF 2.0
-        assert!(gltf_json["asset"]["version"].as_str().unwrap() == "2.0");
+        let version = required_str(&json["asset"], "asset", "version")?;
+        if version != "2.0" {
+            return Err(GltfError::UnsupportedVersion(version.to_string()));
+        }
+        // Optional extensions we don't know are ignored, required ones would render wrong without them
+        for extension in json["extensionsRequired"].members() {
+            let extension = extension.as_str().ok_or_else(|| GltfError::MissingProperty { object: "glTF".to_string(), property: "extensionsRequired" })?;
+            if !SUPPORTED_EXTENSIONS.contains(&extension) {
+                return Err(GltfError::UnsupportedExtension(extension.to_string()));
+            }
+        }
 
         // Load all buffers referenced by this json
         let mut buffers = Vec::<Vec<u8>>::new();
-        for i in 0..gltf_json["buffers"].len() {
-            println!("{:?}", gltf_json["buffers"][i]["uri"]);
-            let buffer_name = gltf_json["buffers"][i]["uri"].as_str().unwrap();
-            buffers.push(fs::read(path.parent().unwrap().join(buffer_name)).unwrap())
+        for (index, buffer) in json["buffers"].members().enumerate() {
+            let byte_length = required_usize(buffer, &format!("buffers[{}]", index), "byteLength")?;
+            let buffer_data = match buffer["uri"].as_str() {
+                Some(uri) => read_uri(path, uri)?,
+                // A buffer without uri refers to the BIN chunk of a .glb
+                None => {
+                    let bin_chunk = bin_chunk.ok_or_else(|| GltfError::Buffer { index, reason: "no uri and there is no glb BIN chunk".to_string() })?;
+                    bin_chunk.get(..byte_length).ok_or_else(|| GltfError::Buffer { index, reason: format!("byteLength {} is larger than the glb BIN chunk ({} bytes)", byte_length, bin_chunk.len()) })?.to_vec()
+                }
+            };
+            if buffer_data.len() < byte_length {
+                return Err(GltfError::Buffer { index, reason: format!("byteLength is {} but only {} bytes were loaded", byte_length, buffer_data.len()) });
+            }
+            buffers.push(buffer_data)
         }
 
-        // println!("{:?}", buffer_data);
+        Ok(Self {
+            path: path.to_path_buf(),
+            json,
+            buffers,
+        })
+    }
 
-        // TODO: Do i need this? does it even matter?
-        // Load accessors
-        let mut accessors = Vec::<&json::JsonValue>::new();
-        for accessor in  gltf_json["accessors"].members() {
-            accessors.push(accessor);
+    pub fn accessor(&self, index: usize) -> Result<&JsonValue, GltfError> {
+        let accessor = &self.json["accessors"][index];
+        match accessor.is_object() {
+            true => Ok(accessor),
+            false => Err(GltfError::Accessor { index, reason: "does not exist".to_string() }),
         }
+    }
 
-        // Load bufferViews
-        let mut buffer_views = Vec::<&json::JsonValue>::new();
-        for buffer_view in gltf_json["bufferViews"].members() {
-            buffer_views.push(buffer_view);
+    pub fn buffer_view(&self, index: usize) -> Result<&JsonValue, GltfError> {
+        let buffer_view = &self.json["bufferViews"][index];
+        match buffer_view.is_object() {
+            true => Ok(buffer_view),
+            false => Err(GltfError::BufferView { index, reason: "does not exist".to_string() }),
         }
+    }
 
-        let mut verts = Vec::<Vertex>::new();
-        let mut indices = Vec::<u16>::new();
+    /// The bytes of a bufferView, bounds checked against its buffer.
+    pub fn buffer_view_data(&self, index: usize) -> Result<&[u8], GltfError> {
+        let buffer_view = self.buffer_view(index)?;
+        let object = format!("bufferViews[{}]", index);
+        let buffer_index = required_usize(buffer_view, &object, "buffer")?;
+        let buffer = self.buffers.get(buffer_index).ok_or_else(|| GltfError::BufferView { index, reason: format!("buffer {} does not exist", buffer_index) })?;
+        let offset = buffer_view["byteOffset"].as_usize().unwrap_or(0);
+        let length = required_usize(buffer_view, &object, "byteLength")?;
+        // Both come straight from the json, so their sum may overflow
+        offset.checked_add(length).and_then(|end| buffer.get(offset..end)).ok_or_else(|| GltfError::BufferView { index, reason: format!("byteOffset {} and byteLength {} run past the end of buffer {} ({} bytes)", offset, length, buffer_index, buffer.len()) })
+    }
+}
+
+impl Mesh {
+    /// Loads every mesh in the file into a single `Mesh`, ignoring the node hierarchy.
+    /// Use `scene::Scene::load_gltf` to get the meshes placed as the scene describes.
+    pub fn load_gltf (path: &std::path::Path) -> Result<Self, GltfError> {
+        let gltf = GltfDocument::open(path)?;
+        let mut mesh = Self {
+            verts: Vec::new(),
+            indices: Indices::U16(Vec::new()),
+            submeshes: Vec::new(),
+            morph_targets: Vec::new(),
+        };
+        for mesh_index in 0..gltf.json["meshes"].len() {
+            mesh.append(Self::from_gltf(&gltf, mesh_index)?);
+        }
+        Ok(mesh)
+    }
+
+    /// Loads a Wavefront OBJ file, each group, object and material change becomes a submesh.
+    /// Submesh materials index into the materials of its MTL libraries, see `scene::Scene::load_obj`.
+    pub fn load_obj(path: &std::path::Path) -> Result<Self, crate::obj::ObjError> {
+        Ok(crate::obj::ObjDocument::open(path)?.mesh)
+    }
+
+    /// Loads an ASCII or binary STL file, each solid becomes a submesh. Without `weld` every facet gets its own
+    /// vertices with the facet normal, which keeps the hard edges of CAD parts. With `weld` corners at the same
+    /// position share a vertex whose normal averages the facets around it, weighted by their area.
+    pub fn load_stl(path: &std::path::Path, weld: bool) -> Result<Self, crate::stl::StlError> {
+        crate::stl::load(path, weld)
+    }
 
-        // Read all meshes
-        let meshes = &gltf_json["meshes"];
-        for i in 0..meshes.len() {
-            let mesh = &meshes[i];
-            let name = &mesh["name"].as_str().unwrap();
+    /// Loads an ASCII or binary PLY file. Vertex positions, normals, colors and uvs are read from the properties
+    /// with their usual names, faces become a single submesh and files without faces load as a point cloud.
+    pub fn load_ply(path: &std::path::Path) -> Result<Self, crate::ply::PlyError> {
+        crate::ply::load(path)
+    }
 
-            // Submeshes, or whatever you want to call them
-            for primitive in mesh["primitives"].members() {
-                // Expect that there is always a position attribute so we use that to figure out the length of our buffer
-                let attributes = &primitive["attributes"];
+    /// Meshes that only have point submeshes, like scans without faces.
+    pub fn is_point_cloud(&self) -> bool {
+        !self.submeshes.is_empty() && self.submeshes.iter().all(|submesh| submesh.topology == Topology::Points)
+    }
 
-                // Handle positions
-                let position_attribute_value = &attributes["POSITION"];
-                assert!(*position_attribute_value != json::Null);
-                let position_accessor_index = position_attribute_value.as_usize().unwrap();
-                let vertex_count = accessors[position_accessor_index]["count"].as_usize().unwrap();
-                
-                // Handle positions
-                let position_buffer = get_attributes_buffer_slice(&buffers, "POSITION", &attributes, &accessors, &buffer_views);
-                // Handle uv
-                let uv_buffer = get_attributes_buffer_slice(&buffers, "TEXCOORD_0", &attributes, &accessors, &buffer_views);
+    /// Loads glTF mesh `mesh_index`, each primitive becomes a submesh.
+    pub(crate) fn from_gltf(gltf: &GltfDocument, mesh_index: usize) -> Result<Self, GltfError> {
+        let mut verts = Vec::<Vertex>::new();
+        let mut indices = Indices::U16(Vec::new());
+        let mut submeshes = Vec::<Submesh>::new();
+        let mut morph_targets = Vec::<MorphTarget>::new();
+        let mesh_error = |reason: String| GltfError::Mesh { index: mesh_index, reason };
 
+        // Submeshes, or whatever you want to call them
+        for (primitive_index, primitive) in gltf.json["meshes"][mesh_index]["primitives"].members().enumerate() {
+            let attributes = &primitive["attributes"];
 
-                // Handle color
+            // Points, lines and strips would be mangled by flat shading and the triangle list pipelines
+            let mode = match &primitive["mode"] {
+                JsonValue::Null => PRIMITIVE_MODE_TRIANGLES,
+                mode => mode.as_usize().ok_or_else(|| GltfError::MissingProperty { object: format!("meshes[{}].primitives[{}]", mesh_index, primitive_index), property: "mode" })?,
+            };
+            if mode != PRIMITIVE_MODE_TRIANGLES && mode != PRIMITIVE_MODE_POINTS {
+                return Err(mesh_error(format!("primitive {} has mode {}, only points (0) and triangles (4) are supported", primitive_index, mode)));
+            }
+            let topology = if mode == PRIMITIVE_MODE_POINTS { Topology::Points } else { Topology::Triangles };
 
-                for i in 0..vertex_count {
-                    println!("{}", i*12);
-                    verts.push(Vertex {
-                        position: [f32_from_buffer_slice(i*12, &position_buffer), f32_from_buffer_slice(i*12+4, &position_buffer), f32_from_buffer_slice(i*12+8, &position_buffer)],
-                        color: [1.0, 1.0, 1.0],
-                        uv: [f32_from_buffer_slice(i*8, &uv_buffer), f32_from_buffer_slice(i*8+4, &uv_buffer)],
-                    })
+            // Expect that there is always a position attribute so we use that to figure out the length of our buffer
+            let positions = read_attribute::<3>(gltf, "POSITION", attributes)?
+                .ok_or_else(|| GltfError::MissingProperty { object: format!("meshes[{}].primitives[{}].attributes", mesh_index, primitive_index), property: "POSITION" })?;
+            let vertex_count = positions.len();
+            // Handle uv
+            let uvs = read_attribute::<2>(gltf, "TEXCOORD_0", attributes)?;
+            let uvs1 = read_attribute::<2>(gltf, "TEXCOORD_1", attributes)?;
+            // Handle normals and tangents
+            let normals = read_attribute::<3>(gltf, "NORMAL", attributes)?;
+            let tangents = read_attribute::<4>(gltf, "TANGENT", attributes)?;
+            // Handle color, which can be either rgb or rgba
+            let colors = match attributes["COLOR_0"].as_usize() {
+                Some(accessor_index) if gltf.accessor(accessor_index)?["type"] == "VEC3" => {
+                    Some(read_accessor_f32::<3>(gltf, accessor_index)?.into_iter().map(|[r, g, b]| [r, g, b, 1.0]).collect::<Vec<_>>())
+                }
+                Some(accessor_index) => Some(read_accessor_f32::<4>(gltf, accessor_index)?),
+                None => None,
+            };
+            // Handle skinning, joints are unsigned bytes or shorts so they convert to f32 exactly
+            let joints = read_attribute::<4>(gltf, "JOINTS_0", attributes)?;
+            let weights = read_attribute::<4>(gltf, "WEIGHTS_0", attributes)?;
+            if joints.is_some() != weights.is_some() {
+                return Err(mesh_error(format!("primitive {} needs both JOINTS_0 and WEIGHTS_0 to be skinned", primitive_index)));
+            }
+            for (name, attribute_len) in [("TEXCOORD_0", uvs.as_ref().map(Vec::len)), ("TEXCOORD_1", uvs1.as_ref().map(Vec::len)), ("NORMAL", normals.as_ref().map(Vec::len)), ("TANGENT", tangents.as_ref().map(Vec::len)), ("COLOR_0", colors.as_ref().map(Vec::len)), ("JOINTS_0", joints.as_ref().map(Vec::len)), ("WEIGHTS_0", weights.as_ref().map(Vec::len))] {
+                if let Some(attribute_len) = attribute_len {
+                    if attribute_len != vertex_count {
+                        return Err(mesh_error(format!("primitive {} has {} {} but {} positions", primitive_index, attribute_len, name, vertex_count)));
+                    }
                 }
+            }
 
-                println!("{:?}", verts);
+            let mut primitive_verts = (0..vertex_count).map(|i| {
+                let mut vertex = Vertex {
+                    position: positions[i],
+                    ..Default::default()
+                };
+                if let Some(uvs) = &uvs {
+                    vertex.uv = uvs[i];
+                }
+                if let Some(uvs1) = &uvs1 {
+                    vertex.uv1 = uvs1[i];
+                }
+                if let Some(normals) = &normals {
+                    vertex.normal = normals[i];
+                }
+                if let Some(tangents) = &tangents {
+                    vertex.tangent = tangents[i];
+                }
+                if let Some(colors) = &colors {
+                    vertex.color = colors[i];
+                }
+                if let (Some(joints), Some(weights)) = (&joints, &weights) {
+                    vertex.joints = joints[i].map(|joint| joint as u32);
+                    // Weights should sum to one but quantized weights rarely do exactly
+                    let total = weights[i].iter().sum::<f32>();
+                    if total > 0.0 {
+                        vertex.weights = weights[i].map(|weight| weight / total);
+                    }
+                }
+                vertex
+            }).collect::<Vec<_>>();
 
-                // for attribute in primitive["attributes"].entries() {
-                //     let accessor_index = attribute.1.as_usize().unwrap();
-                //     // println!("Key: {:?} value: {:?}", attribute.0, accessor_index);
+            let mut primitive_indices = match primitive["indices"].as_usize() {
+                Some(indices_accessor_index) => {
+                    let primitive_indices = read_accessor_indices(gltf, indices_accessor_index)?;
+                    if let Some(index) = primitive_indices.iter().find(|index| *index as usize >= vertex_count) {
+                        return Err(GltfError::Accessor { index: indices_accessor_index, reason: format!("index {} is out of range for {} vertices", index, vertex_count) });
+                    }
+                    primitive_indices
+                }
+                // Non-indexed primitives draw their vertices in order
+                None => Indices::sequential(vertex_count),
+            };
 
-                //     let accessor = accessors[accessor_index];
-                //     let buffer_view_index = accessor["bufferView"].as_usize().unwrap();
+            // Morph targets, the spec requires every primitive of a mesh to have the same number of them
+            let mut primitive_targets = Vec::new();
+            for (target_index, target) in primitive["targets"].members().enumerate() {
+                let mut morph_target = MorphTarget::zeroed(vertex_count);
+                for (name, deltas) in [("POSITION", &mut morph_target.positions), ("NORMAL", &mut morph_target.normals), ("TANGENT", &mut morph_target.tangents)] {
+                    if let Some(target_deltas) = read_attribute::<3>(gltf, name, target)? {
+                        if target_deltas.len() != vertex_count {
+                            return Err(mesh_error(format!("primitive {} target {} has {} {} but {} positions", primitive_index, target_index, target_deltas.len(), name, vertex_count)));
+                        }
+                        *deltas = target_deltas;
+                    }
+                }
+                primitive_targets.push(morph_target);
+            }
+            // Splats are drawn without morphing
+            if topology == Topology::Points && !primitive_targets.is_empty() {
+                return Err(mesh_error(format!("primitive {} has points with morph targets", primitive_index)));
+            }
+            if primitive_index > 0 && primitive_targets.len() != morph_targets.len() {
+                return Err(mesh_error(format!("primitive {} has {} morph targets but primitive 0 has {}", primitive_index, primitive_targets.len(), morph_targets.len())));
+            }
 
-                //     let vertex_count = accessor["count"].as_usize().unwrap();
+            // Points have no faces to shade or to build tangents from
+            if normals.is_none() && topology == Topology::Triangles {
+                primitive_verts = flat_shaded(&primitive_verts, &primitive_indices);
+                // Flat shading duplicated the vertices, the targets have to follow
+                for morph_target in &mut primitive_targets {
+                    let remap = |deltas: &Vec<[f32; 3]>| primitive_indices.iter().map(|index| deltas[index as usize]).collect::<Vec<_>>();
+                    *morph_target = MorphTarget {
+                        positions: remap(&morph_target.positions),
+                        // The face normals are recomputed from the base positions only
+                        normals: vec![[0.0; 3]; primitive_indices.len()],
+                        tangents: remap(&morph_target.tangents),
+                    };
+                }
+                primitive_indices = Indices::sequential(primitive_verts.len());
+            }
+            if tangents.is_none() && uvs.is_some() && topology == Topology::Triangles {
+                generate_tangents(&mut primitive_verts, &primitive_indices);
+            }
 
-                //     let buffer_index = buffer_views[buffer_view_index]["buffer"].as_usize().unwrap();
-                //     let buffer_length = buffer_views[buffer_view_index]["byteLength"].as_usize().unwrap();
-                //     let buffer_offset = buffer_views[buffer_view_index]["byteOffset"].as_usize().unwrap();
+            if primitive_index == 0 {
+                morph_targets = primitive_targets;
+            } else {
+                for (morph_target, primitive_target) in morph_targets.iter_mut().zip(primitive_targets) {
+                    morph_target.extend(primitive_target);
+                }
+            }
 
-                //     println!("buffer: {:?}, length: {:?}, offset: {:?}", buffer_index, buffer_length, buffer_offset);
-                // }
+            let base_vertex = verts.len() as i32;
+            verts.extend(primitive_verts);
+            let index_start = indices.len() as u32;
+            let index_count = primitive_indices.len() as u32;
+            indices.append(primitive_indices);
 
-                let indices_accessor_index = primitive["indices"].as_usize().unwrap();
-                let indices_buffer_index = buffer_views[indices_accessor_index]["buffer"].as_usize().unwrap();
-                let indices_buffer_length = buffer_views[indices_accessor_index]["byteLength"].as_usize().unwrap();
-                let indices_buffer_offset = buffer_views[indices_accessor_index]["byteOffset"].as_usize().unwrap();
-                println!("buffer: {:?}, length: {:?}, offset: {:?}", indices_buffer_index, indices_buffer_length, indices_buffer_offset);
-                let index_count = accessors[indices_accessor_index]["count"].as_usize().unwrap();
-                let index_buffer = &buffers[indices_buffer_index][indices_buffer_offset..indices_buffer_offset+indices_buffer_length];
-                for i in 0..index_count {
-                    indices.push(index_buffer[i*2] as u16 + ((index_buffer[i*2+1] as u16) << 8));
+            let mut variant_materials = Vec::new();
+            let mappings_object = format!("meshes[{}].primitives[{}].extensions.KHR_materials_variants.mappings", mesh_index, primitive_index);
+            for (mapping_index, mapping) in primitive["extensions"]["KHR_materials_variants"]["mappings"].members().enumerate() {
+                let mapping_object = format!("{}[{}]", mappings_object, mapping_index);
+                let material = required_usize(mapping, &mapping_object, "material")?;
+                if !mapping["variants"].is_array() {
+                    return Err(GltfError::MissingProperty { object: mapping_object, property: "variants" });
+                }
+                for variant in mapping["variants"].members() {
+                    let variant = variant.as_usize().ok_or_else(|| GltfError::MissingProperty { object: mapping_object.clone(), property: "variants" })?;
+                    variant_materials.push((variant, material));
                 }
-                println!("{:?}", indices);
             }
 
-            
-            // for attribute in mesh["primitives"]["attributes"].entries() {
-            //     println!("Key: {:?} value: {:?}", attribute.0, attribute.1)
-            // }
+            submeshes.push(Submesh {
+                index_range: index_start..index_start + index_count,
+                topology,
+                base_vertex,
+                material: primitive["material"].as_usize(),
+                variant_materials,
+            });
         }
-        Self {
+
+        Ok(Self {
             indices,
             verts,
+            submeshes,
+            morph_targets,
+        })
+    }
+
+    /// Appends the vertices, indices and submeshes of `other`, keeping its submeshes pointing at its own data.
+    pub fn append(&mut self, other: Mesh) {
+        let base_vertex = self.verts.len() as i32;
+        let index_start = self.indices.len() as u32;
+        // Meshes with fewer targets get zero deltas for the ones they lack
+        let target_count = self.morph_targets.len().max(other.morph_targets.len());
+        self.morph_targets.resize_with(target_count, || MorphTarget::zeroed(self.verts.len()));
+        let mut other_targets = other.morph_targets.into_iter();
+        for morph_target in &mut self.morph_targets {
+            morph_target.extend(other_targets.next().unwrap_or_else(|| MorphTarget::zeroed(other.verts.len())));
+        }
+        self.verts.extend(other.verts);
+        self.indices.append(other.indices);
+        self.submeshes.extend(other.submeshes.into_iter().map(|submesh| Submesh {
+            index_range: submesh.index_range.start + index_start..submesh.index_range.end + index_start,
+            topology: submesh.topology,
+            base_vertex: submesh.base_vertex + base_vertex,
+            material: submesh.material,
+            variant_materials: submesh.variant_materials,
+        }));
+    }
+}
+
+/// GPU buffers for a `Mesh`.
+pub struct GpuMesh {
+    pub vertex_buffer: wgpu::Buffer,
+    /// Morph target deltas as read by the shader's `MorphTargets`, None when the mesh has no targets
+    pub morph_buffer: Option<wgpu::Buffer>,
+    /// Position and color of every point of the point submeshes as read by the splat shader's `Splat`, None when there are none
+    pub splat_buffer: Option<wgpu::Buffer>,
+    pub splat_count: u32,
+    pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat,
+    pub submeshes: Vec<Submesh>,
+}
+
+impl GpuMesh {
+    pub fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex buffer"),
+            contents: bytemuck::cast_slice(&mesh.verts),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index buffer"),
+            contents: &mesh.indices.gpu_data(),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let morph_buffer = (!mesh.morph_targets.is_empty()).then(|| {
+            // Header of vertex count and target count, padded to 16 bytes, then position, normal and tangent deltas for every vertex of every target
+            let mut data = vec![mesh.verts.len() as u32, mesh.morph_targets.len() as u32, 0, 0];
+            for morph_target in &mesh.morph_targets {
+                for vertex in 0..mesh.verts.len() {
+                    for delta in [morph_target.positions[vertex], morph_target.normals[vertex], morph_target.tangents[vertex]] {
+                        data.extend(delta.map(f32::to_bits));
+                        data.push(0);
+                    }
+                }
+            }
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Morph target buffer"),
+                contents: bytemuck::cast_slice(&data),
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        });
+
+        // Splats are drawn without an index buffer, so the points are resolved up front
+        let splats = mesh.submeshes.iter().filter(|submesh| submesh.topology == Topology::Points).flat_map(|submesh| {
+            submesh.index_range.clone().map(|i| &mesh.verts[(mesh.indices.get(i as usize) as i64 + submesh.base_vertex as i64) as usize])
+        }).collect::<Vec<_>>();
+        let splat_count = splats.len() as u32;
+        let splat_buffer = (!splats.is_empty()).then(|| {
+            let data = splats.iter().flat_map(|vertex| {
+                let [x, y, z] = vertex.position;
+                let [r, g, b, a] = vertex.color;
+                [x, y, z, 1.0, r, g, b, a]
+            }).collect::<Vec<f32>>();
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Splat buffer"),
+                contents: bytemuck::cast_slice(&data),
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        });
+
+        Self {
+            vertex_buffer,
+            morph_buffer,
+            splat_buffer,
+            splat_count,
+            index_buffer,
+            index_format: mesh.indices.index_format(),
+            submeshes: mesh.submeshes.clone(),
         }
     }
 }
@@ -190,6 +1207,14 @@ pub struct InstanceData {
     transform: [[f32; 4]; 4],
 }
 
+impl From<cgmath::Matrix4<f32>> for InstanceData {
+    fn from(transform: cgmath::Matrix4<f32>) -> Self {
+        Self {
+            transform: transform.into(),
+        }
+    }
+}
+
 impl InstanceData {
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
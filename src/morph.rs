@@ -0,0 +1,73 @@
+use crate::mesh;
+use crate::skin;
+
+/// Morph target weights of every morphed draw, packed into one storage buffer and bound with a dynamic offset per draw.
+/// Each mesh with morph targets gets its own bind group that pairs its deltas with the shared weights.
+pub struct MorphWeights {
+    buffer: wgpu::Buffer,
+    /// Per `gpu_meshes` entry, None for meshes without morph targets
+    pub bind_groups: Vec<Option<wgpu::BindGroup>>,
+    /// Dynamic offset of each weight list, in the order they were passed to `new`
+    pub offsets: Vec<u32>,
+}
+
+impl MorphWeights {
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let storage = |binding, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Morph targets bind group layout"),
+            entries: &[storage(0, false), storage(1, true)],
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, gpu_meshes: &[mesh::GpuMesh], weights: &[Vec<f32>]) -> Self {
+        let (data, offsets, binding_size) = skin::pack_dynamic(device, weights);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Morph weights buffer"),
+            size: data.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true,
+        });
+        buffer.slice(..).get_mapped_range_mut().copy_from_slice(&data);
+        buffer.unmap();
+
+        let bind_groups = gpu_meshes.iter().map(|gpu_mesh| {
+            let morph_buffer = gpu_mesh.morph_buffer.as_ref()?;
+            Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Morph targets bind group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: morph_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(binding_size),
+                        }),
+                    },
+                ],
+            }))
+        }).collect();
+
+        Self { buffer, bind_groups, offsets }
+    }
+
+    /// Writes new weights, there must be as many weight lists as the palette was created with and none may be longer than before.
+    pub fn update(&self, device: &wgpu::Device, queue: &wgpu::Queue, weights: &[Vec<f32>]) {
+        let (data, _, _) = skin::pack_dynamic(device, weights);
+        queue.write_buffer(&self.buffer, 0, &data);
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+
+use crate::material;
+use crate::mesh::{self, Indices, Mesh, Submesh, Topology, Vertex};
+
+/// Why loading an OBJ file or one of its MTL libraries failed.
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: std::path::PathBuf, source: std::io::Error },
+    /// A malformed statement, `line` counts from 1
+    Parse { path: std::path::PathBuf, line: usize, reason: String },
+    /// A texture map that couldn't be decoded
+    Image { path: std::path::PathBuf, reason: String },
+}
+
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "failed to read {}: {}", path.display(), source),
+            ObjError::Parse { path, line, reason } => write!(f, "{}:{}: {}", path.display(), line, reason),
+            ObjError::Image { path, reason } => write!(f, "failed to decode {}: {}", path.display(), reason),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The statements of an OBJ or MTL file as (line number, keyword, arguments), with comments,
+/// blank lines and `\` line continuations already dealt with.
+fn statements(path: &std::path::Path) -> Result<Vec<(usize, String, Vec<String>)>, ObjError> {
+    let data = std::fs::read(path).map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+    // Legacy exporters write names in whatever codepage they ran in, don't fail over a comment
+    let text = String::from_utf8_lossy(&data);
+    let mut statements = Vec::new();
+    let mut pending = String::new();
+    let mut pending_line = 0;
+    for (i, line) in text.lines().enumerate() {
+        if pending.is_empty() {
+            pending_line = i + 1;
+        }
+        let line = line.split('#').next().unwrap_or("");
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            pending.push(' ');
+            continue;
+        }
+        pending.push_str(line);
+        let mut tokens = pending.split_whitespace().map(String::from);
+        if let Some(keyword) = tokens.next() {
+            statements.push((pending_line, keyword, tokens.collect()));
+        }
+        pending.clear();
+    }
+    Ok(statements)
+}
+
+fn parse_f32(path: &std::path::Path, line: usize, token: &str) -> Result<f32, ObjError> {
+    token.parse().map_err(|_| ObjError::Parse { path: path.to_path_buf(), line, reason: format!("invalid number {}", token) })
+}
+
+/// Parses `args` as numbers, at least `required` of them and at most `N`, the rest keep their value in `defaults`.
+fn parse_floats<const N: usize>(path: &std::path::Path, line: usize, args: &[String], required: usize, defaults: [f32; N]) -> Result<[f32; N], ObjError> {
+    if args.len() < required {
+        return Err(ObjError::Parse { path: path.to_path_buf(), line, reason: format!("expected {} numbers but found {}", required, args.len()) });
+    }
+    let mut values = defaults;
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = parse_f32(path, line, arg)?;
+    }
+    Ok(values)
+}
+
+/// Materials, textures and images of the MTL libraries an OBJ file references.
+#[derive(Default)]
+struct MaterialLibrary {
+    materials: Vec<material::Material>,
+    textures: Vec<material::ImageTexture>,
+    images: Vec<image::DynamicImage>,
+    /// Images already decoded, maps referencing the same file share them
+    image_paths: HashMap<std::path::PathBuf, usize>,
+}
+
+impl MaterialLibrary {
+    /// Reads the materials of the MTL file at `path`. OBJ materials are Phong based, only the diffuse color,
+    /// opacity, diffuse map and bump map carry over, the rest of the material keeps its glTF defaults.
+    fn load(&mut self, path: &std::path::Path) -> Result<(), ObjError> {
+        let mut material: Option<material::Material> = None;
+        for (line, keyword, args) in statements(path)? {
+            if keyword == "newmtl" {
+                self.finish(material.take());
+                material = Some(material::Material {
+                    name: Some(args.join(" ")),
+                    // Phong materials are dielectrics
+                    metallic_factor: 0.0,
+                    ..Default::default()
+                });
+                continue;
+            }
+            let Some(material) = material.as_mut() else {
+                // Statements before the first newmtl have no material to apply to
+                continue;
+            };
+            match keyword.as_str() {
+                "Kd" => {
+                    let [r, g, b] = parse_floats(path, line, &args, 3, [0.0; 3])?;
+                    material.base_color_factor = [r, g, b, material.base_color_factor[3]];
+                }
+                // d is opacity and Tr its inverse, when a file has both the last one wins
+                "d" => material.base_color_factor[3] = parse_floats(path, line, &args, 1, [1.0])?[0],
+                "Tr" => material.base_color_factor[3] = 1.0 - parse_floats(path, line, &args, 1, [0.0])?[0],
+                "map_Kd" => {
+                    let (texture_ref, _) = self.texture_map(path, line, &args)?;
+                    material.base_color_texture = Some(texture_ref);
+                }
+                // Most exporters write tangent space normal maps here even though the format means height maps
+                "map_Bump" | "map_bump" | "bump" => {
+                    let (texture_ref, bump_multiplier) = self.texture_map(path, line, &args)?;
+                    material.normal_texture = Some(texture_ref);
+                    material.normal_scale = bump_multiplier;
+                }
+                // Specular, ambient and the many vendor statements have no counterpart
+                _ => {}
+            }
+        }
+        self.finish(material);
+        Ok(())
+    }
+
+    fn finish(&mut self, material: Option<material::Material>) {
+        if let Some(mut material) = material {
+            if material.base_color_factor[3] < 1.0 {
+                material.alpha_mode = material::AlphaMode::Blend;
+            }
+            self.materials.push(material);
+        }
+    }
+
+    /// Parses the options and file name of a `map_*` statement, returning the texture and the `-bm` bump multiplier.
+    fn texture_map(&mut self, mtl_path: &std::path::Path, line: usize, args: &[String]) -> Result<(material::TextureRef, f32), ObjError> {
+        let mut transform = material::TextureTransform::default();
+        let mut offset = [0.0; 2];
+        let mut sampler = material::Sampler::default();
+        let mut bump_multiplier = 1.0;
+        let mut args = args.iter().peekable();
+        // Every option is a dash followed by a fixed number of arguments, except -o, -s and -t which take one to three numbers
+        while let Some(option) = args.next_if(|arg| arg.starts_with('-')) {
+            let mut numbers = Vec::new();
+            let max_count = match option.as_str() {
+                "-o" | "-s" | "-t" => 3,
+                "-mm" => 2,
+                _ => 1,
+            };
+            while numbers.len() < max_count {
+                match args.peek().and_then(|arg| arg.parse::<f32>().ok()) {
+                    Some(number) => {
+                        numbers.push(number);
+                        args.next();
+                    }
+                    None => break,
+                }
+            }
+            match option.as_str() {
+                "-o" => offset = [numbers.first().copied().unwrap_or(0.0), numbers.get(1).copied().unwrap_or(0.0)],
+                "-s" => transform.scale = [numbers.first().copied().unwrap_or(1.0), numbers.get(1).copied().unwrap_or(1.0)],
+                "-bm" => bump_multiplier = numbers.first().copied().unwrap_or(1.0),
+                "-clamp" => {
+                    if args.next().map(String::as_str) == Some("on") {
+                        sampler.address_mode_u = wgpu::AddressMode::ClampToEdge;
+                        sampler.address_mode_v = wgpu::AddressMode::ClampToEdge;
+                    }
+                }
+                // Options without an equivalent, their on/off or channel argument is skipped
+                "-blendu" | "-blendv" | "-cc" | "-imfchan" => {
+                    args.next();
+                }
+                "-t" | "-mm" | "-boost" | "-texres" => {}
+                _ => return Err(ObjError::Parse { path: mtl_path.to_path_buf(), line, reason: format!("unknown texture option {}", option) }),
+            }
+        }
+        // Uvs are flipped to glTF's top left origin, so the offset has to be mirrored along with them
+        transform.offset = [offset[0], 1.0 - transform.scale[1] - offset[1]];
+
+        // File names may contain spaces, and files written on Windows use backslashes
+        let file_name = args.map(String::as_str).collect::<Vec<_>>().join(" ").replace('\\', "/");
+        if file_name.is_empty() {
+            return Err(ObjError::Parse { path: mtl_path.to_path_buf(), line, reason: "texture map without a file name".to_string() });
+        }
+        let image_path = mtl_path.parent().unwrap_or(std::path::Path::new("")).join(file_name);
+        let image = match self.image_paths.get(&image_path) {
+            Some(image) => *image,
+            None => {
+                let data = std::fs::read(&image_path).map_err(|source| ObjError::Io { path: image_path.clone(), source })?;
+                let decoded = image::load_from_memory(&data).map_err(|error| ObjError::Image { path: image_path.clone(), reason: error.to_string() })?;
+                self.images.push(decoded);
+                self.image_paths.insert(image_path, self.images.len() - 1);
+                self.images.len() - 1
+            }
+        };
+        // Color space is decided by the material slot, so maps of one image with the same options can share a texture
+        let texture = match self.textures.iter().position(|texture| texture.image == image && texture.sampler == sampler) {
+            Some(texture) => texture,
+            None => {
+                self.textures.push(material::ImageTexture { image, sampler });
+                self.textures.len() - 1
+            }
+        };
+        Ok((material::TextureRef { texture, tex_coord: 0, transform }, bump_multiplier))
+    }
+}
+
+/// Where the normal of a face corner comes from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum CornerNormal {
+    /// Index of a `vn` statement
+    File(usize),
+    /// Index of a normal computed for a face whose corners don't all have one
+    Face(usize),
+}
+
+/// A parsed OBJ file with the materials of its MTL libraries.
+pub(crate) struct ObjDocument {
+    /// Every group and material change starts a new submesh
+    pub mesh: Mesh,
+    /// Submesh materials index into these, in the order the MTL libraries define them
+    pub materials: Vec<material::Material>,
+    pub textures: Vec<material::ImageTexture>,
+    pub images: Vec<image::DynamicImage>,
+}
+
+impl ObjDocument {
+    /// Parses the OBJ file at `path` and the MTL libraries it references, resolved relative to it.
+    /// `usemtl` with a name no library defines draws with the default material, as most viewers do.
+    pub fn open(path: &std::path::Path) -> Result<Self, ObjError> {
+        let mut library = MaterialLibrary::default();
+        let mut positions = Vec::<[f32; 3]>::new();
+        // Not part of the format, but a widespread extension puts an rgb color after the position
+        let mut colors = Vec::<[f32; 4]>::new();
+        let mut uvs = Vec::<[f32; 2]>::new();
+        let mut normals = Vec::<[f32; 3]>::new();
+        // Kept apart from `normals` so later `vn` indices can't resolve to them
+        let mut face_normals = Vec::<[f32; 3]>::new();
+
+        let mut verts = Vec::<Vertex>::new();
+        let mut indices = Vec::<u32>::new();
+        // Corners referencing the same position, uv and normal share a vertex
+        let mut vertex_lookup = HashMap::<(usize, Option<usize>, CornerNormal), u32>::new();
+        let mut submeshes = Vec::<Submesh>::new();
+        let mut submesh_start = 0;
+        let mut material = None;
+        let mut has_uvs = false;
+
+        let flush = |submeshes: &mut Vec<Submesh>, submesh_start: &mut usize, end: usize, material: Option<usize>| {
+            if end > *submesh_start {
+                submeshes.push(Submesh {
+                    index_range: *submesh_start as u32..end as u32,
+                    topology: Topology::Triangles,
+                    base_vertex: 0,
+                    material,
+                    variant_materials: Vec::new(),
+                });
+            }
+            *submesh_start = end;
+        };
+
+        for (line, keyword, args) in statements(path)? {
+            let parse_error = |reason: String| ObjError::Parse { path: path.to_path_buf(), line, reason };
+            match keyword.as_str() {
+                "v" => {
+                    let [x, y, z, r, g, b] = parse_floats(path, line, &args, 3, [0.0, 0.0, 0.0, 1.0, 1.0, 1.0])?;
+                    // A fourth number alone is the rarely used homogeneous w, not a color
+                    let color = if args.len() >= 6 { [r, g, b, 1.0] } else { [1.0; 4] };
+                    positions.push([x, y, z]);
+                    colors.push(color);
+                }
+                "vt" => {
+                    uvs.push(mesh::uv_from_bottom_left(parse_floats(path, line, &args, 1, [0.0; 2])?));
+                }
+                "vn" => normals.push(parse_floats(path, line, &args, 3, [0.0; 3])?),
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(parse_error(format!("face with {} corners", args.len())));
+                    }
+                    // Indices count from 1, negative ones count back from the last element defined so far
+                    let resolve = |token: &str, count: usize, element: &str| -> Result<usize, ObjError> {
+                        let index = token.parse::<i64>().map_err(|_| parse_error(format!("invalid {} index {}", element, token)))?;
+                        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+                        if resolved < 0 || resolved >= count as i64 {
+                            return Err(parse_error(format!("{} index {} is out of range for {} {}s", element, index, count, element)));
+                        }
+                        Ok(resolved as usize)
+                    };
+                    let mut corners = Vec::with_capacity(args.len());
+                    for arg in &args {
+                        // v, v/vt, v//vn or v/vt/vn
+                        let mut parts = arg.split('/');
+                        let position = resolve(parts.next().unwrap_or(""), positions.len(), "position")?;
+                        let uv = parts.next().filter(|part| !part.is_empty()).map(|part| resolve(part, uvs.len(), "uv")).transpose()?;
+                        let normal = parts.next().filter(|part| !part.is_empty()).map(|part| resolve(part, normals.len(), "normal").map(CornerNormal::File)).transpose()?;
+                        corners.push((position, uv, normal));
+                    }
+                    has_uvs |= corners.iter().any(|(_, uv, _)| uv.is_some());
+
+                    // Corners without a normal get the face normal, like glTF primitives without normals
+                    if corners.iter().any(|(_, _, normal)| normal.is_none()) {
+                        face_normals.push(face_normal(corners.iter().map(|(position, _, _)| positions[*position])));
+                        let face_normal = CornerNormal::Face(face_normals.len() - 1);
+                        for (_, _, normal) in &mut corners {
+                            normal.get_or_insert(face_normal);
+                        }
+                    }
+                    let corner_indices = corners.into_iter().map(|(position, uv, normal)| {
+                        // Every corner got a normal above
+                        let normal = normal.unwrap();
+                        *vertex_lookup.entry((position, uv, normal)).or_insert_with(|| {
+                            verts.push(Vertex {
+                                position: positions[position],
+                                color: colors[position],
+                                uv: uv.map_or([0.0; 2], |uv| uvs[uv]),
+                                normal: match normal {
+                                    CornerNormal::File(normal) => normals[normal],
+                                    CornerNormal::Face(normal) => face_normals[normal],
+                                },
+                                ..Default::default()
+                            });
+                            verts.len() as u32 - 1
+                        })
+                    }).collect::<Vec<_>>();
+                    mesh::triangulate_fan(&corner_indices, &mut indices);
+                }
+                "g" | "o" => flush(&mut submeshes, &mut submesh_start, indices.len(), material),
+                "usemtl" => {
+                    flush(&mut submeshes, &mut submesh_start, indices.len(), material);
+                    let name = args.join(" ");
+                    material = library.materials.iter().position(|material| material.name.as_deref() == Some(name.as_str()));
+                }
+                "mtllib" => {
+                    for file_name in &args {
+                        library.load(&path.parent().unwrap_or(std::path::Path::new("")).join(file_name.replace('\\', "/")))?;
+                    }
+                }
+                // Smoothing groups, lines, points and free-form geometry aren't drawn
+                _ => {}
+            }
+        }
+        flush(&mut submeshes, &mut submesh_start, indices.len(), material);
+
+        let indices = Indices::from_u32(indices);
+        if has_uvs {
+            mesh::generate_tangents(&mut verts, &indices);
+        }
+
+        Ok(Self {
+            mesh: Mesh {
+                verts,
+                indices,
+                submeshes,
+                morph_targets: Vec::new(),
+            },
+            materials: library.materials,
+            textures: library.textures,
+            images: library.images,
+        })
+    }
+}
+
+/// Newell's method, robust for polygons that aren't quite planar.
+fn face_normal(corners: impl Iterator<Item = [f32; 3]> + Clone) -> [f32; 3] {
+    use cgmath::InnerSpace;
+    let mut normal = cgmath::vec3(0.0f32, 0.0, 0.0);
+    let next = corners.clone().cycle().skip(1);
+    for (current, next) in corners.zip(next) {
+        normal.x += (current[1] - next[1]) * (current[2] + next[2]);
+        normal.y += (current[2] - next[2]) * (current[0] + next[0]);
+        normal.z += (current[0] - next[0]) * (current[1] + next[1]);
+    }
+    // Degenerate faces get an arbitrary normal rather than NaNs
+    if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0, 0.0, 1.0] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` into a fresh temporary directory and parses the first one as the OBJ file.
+    fn open(name: &str, files: &[(&str, &[u8])]) -> ObjDocument {
+        let directory = std::env::temp_dir().join(format!("webgpu_renderer_obj_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file_name, data) in files {
+            std::fs::write(directory.join(file_name), data).unwrap();
+        }
+        let obj = ObjDocument::open(&directory.join(files[0].0));
+        std::fs::remove_dir_all(&directory).unwrap();
+        obj.unwrap()
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[[f32; 3]; 3]> {
+        let indices = mesh.indices.iter().collect::<Vec<_>>();
+        indices.chunks_exact(3).map(|triangle| [0, 1, 2].map(|corner| mesh.verts[triangle[corner] as usize].position)).collect()
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let obj = open("negative", &[("mesh.obj", b"
+v 9 9 9
+v 0 0 0
+v 1 0 0
+v 1 1 0
+vn 0 0 -1
+vn 0 0 1
+f -3//-1 -2//-1 -1//-1
+")]);
+        assert_eq!(triangles(&obj.mesh), [[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]]);
+        assert!(obj.mesh.verts.iter().all(|vertex| vertex.normal == [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn backslash_continues_a_statement() {
+        let obj = open("continuation", &[("mesh.obj", b"
+v 0 0 0
+v 1 \\
+  0 0 # the rest of v 2
+v 1 1 0
+f 1 \\
+2 \\
+3
+")]);
+        assert_eq!(triangles(&obj.mesh), [[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]]);
+    }
+
+    #[test]
+    fn groups_and_materials_split_submeshes() {
+        let obj = open("submeshes", &[
+            ("mesh.obj", b"
+mtllib mesh.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+f 1 2 3 4
+g second
+usemtl Red
+f 1 2 3
+o third
+f 1 3 4
+usemtl Missing
+f 2 3 4
+"),
+            ("mesh.mtl", b"newmtl Red\nKd 1 0 0\n"),
+        ]);
+        let submeshes = obj.mesh.submeshes.iter().map(|submesh| (submesh.index_range.clone(), submesh.material)).collect::<Vec<_>>();
+        // The quad is fanned into two triangles, `g` directly followed by `usemtl` doesn't leave an empty submesh
+        assert_eq!(submeshes, [(0..6, None), (6..9, Some(0)), (9..12, Some(0)), (12..15, None)]);
+        assert_eq!(obj.materials[0].base_color_factor, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn missing_normals_use_the_face_normal_without_touching_vn() {
+        let obj = open("face_normals", &[("mesh.obj", b"
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+vn 1 0 0
+f 1//1 2//1 3//1
+")]);
+        let normals = obj.mesh.verts.iter().map(|vertex| vertex.normal).collect::<Vec<_>>();
+        // Counter-clockwise in the xy plane faces +z, the second face keeps its own vn
+        assert_eq!(normals, [[0.0, 0.0, 1.0]; 3].into_iter().chain([[1.0, 0.0, 0.0]; 3]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn texture_offset_is_mirrored_with_the_flipped_uvs() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgba8(1, 1).write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+        let obj = open("texture_offset", &[
+            ("mesh.obj", b"mtllib mesh.mtl\n"),
+            ("mesh.mtl", b"newmtl Textured\nmap_Kd -o 0.25 0.125 -s 0.5 0.25 -clamp on texture file.png\n"),
+            ("texture file.png", &png),
+        ]);
+        let texture = obj.materials[0].base_color_texture.as_ref().unwrap();
+        assert_eq!(texture.transform.scale, [0.5, 0.25]);
+        // 1 - scale - offset: v 0.125 to 0.375 from the bottom is 0.625 to 0.875 from the top
+        assert_eq!(texture.transform.offset, [0.25, 0.625]);
+        assert_eq!(obj.textures[texture.texture].sampler.address_mode_u, wgpu::AddressMode::ClampToEdge);
+        assert_eq!(obj.images.len(), 1);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::mesh::{self, Indices, Mesh, Submesh, Topology, Vertex};
+use crate::texture;
+
+/// Why loading a PLY file failed.
+#[derive(Debug)]
+pub enum PlyError {
+    Io { path: std::path::PathBuf, source: std::io::Error },
+    /// A malformed header line, `line` counts from 1
+    Header { path: std::path::PathBuf, line: usize, reason: String },
+    /// Element data that doesn't match the header
+    Data { path: std::path::PathBuf, element: String, index: usize, reason: String },
+}
+
+impl std::fmt::Display for PlyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlyError::Io { path, source } => write!(f, "failed to read {}: {}", path.display(), source),
+            PlyError::Header { path, line, reason } => write!(f, "{}:{}: {}", path.display(), line, reason),
+            PlyError::Data { path, element, index, reason } => write!(f, "{}: {} {}: {}", path.display(), element, index, reason),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlyError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    /// Both the names of the original spec and the sized names most writers use today.
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// What a color channel of this type is divided by to get 0..1, floats are taken as is.
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::I8 => i8::MAX as f64,
+            ScalarType::U8 => u8::MAX as f64,
+            ScalarType::I16 => i16::MAX as f64,
+            ScalarType::U16 => u16::MAX as f64,
+            ScalarType::I32 => i32::MAX as f64,
+            ScalarType::U32 => u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar { name: String, scalar_type: ScalarType },
+    /// A count followed by that many items
+    List { name: String, count_type: ScalarType, item_type: ScalarType },
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar { name, .. } | Property::List { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    /// Header line that declared the element
+    line: usize,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Where a vertex property ends up in `Vertex`, properties without a place are read and dropped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Target {
+    Position(usize),
+    Normal(usize),
+    Color(usize),
+    Uv(usize),
+    Ignored,
+}
+
+impl Target {
+    fn of(name: &str) -> Self {
+        match name {
+            "x" => Target::Position(0),
+            "y" => Target::Position(1),
+            "z" => Target::Position(2),
+            "nx" | "normal_x" => Target::Normal(0),
+            "ny" | "normal_y" => Target::Normal(1),
+            "nz" | "normal_z" => Target::Normal(2),
+            "red" | "r" | "diffuse_red" => Target::Color(0),
+            "green" | "g" | "diffuse_green" => Target::Color(1),
+            "blue" | "b" | "diffuse_blue" => Target::Color(2),
+            "alpha" | "a" | "diffuse_alpha" => Target::Color(3),
+            "u" | "s" | "texture_u" | "texture_s" => Target::Uv(0),
+            "v" | "t" | "texture_v" | "texture_t" => Target::Uv(1),
+            _ => Target::Ignored,
+        }
+    }
+}
+
+/// Reads the scalars of the element data, in whichever format the header declared.
+struct Reader<'a> {
+    format: Format,
+    data: &'a [u8],
+    offset: usize,
+    /// Whitespace separated values of an ASCII body
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(format: Format, data: &'a [u8], text: &'a str) -> Self {
+        Self { format, data, offset: 0, tokens: text.split_ascii_whitespace() }
+    }
+
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.next().ok_or_else(|| "unexpected end of data".to_string())?;
+            return token.parse().map_err(|_| format!("invalid number {}", token));
+        }
+        let size = scalar_type.size();
+        let bytes = self.data.get(self.offset..self.offset + size).ok_or_else(|| "unexpected end of data".to_string())?;
+        self.offset += size;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+        let [b0, b1, b2, b3, ..] = buffer;
+        Ok(match scalar_type {
+            ScalarType::I8 => b0 as i8 as f64,
+            ScalarType::U8 => b0 as f64,
+            ScalarType::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(buffer),
+        })
+    }
+}
+
+/// Parses the header, returning the format, the elements and the offset of the element data.
+fn read_header(path: &std::path::Path, data: &[u8]) -> Result<(Format, Vec<Element>, usize), PlyError> {
+    let mut format = None;
+    let mut elements = Vec::<Element>::new();
+    let mut offset = 0;
+    let mut line_number = 0;
+    loop {
+        line_number += 1;
+        let header_error = |reason: String| PlyError::Header { path: path.to_path_buf(), line: line_number, reason };
+        let line_end = data[offset..].iter().position(|byte| *byte == b'\n').ok_or_else(|| header_error("header without end_header".to_string()))?;
+        let line = String::from_utf8_lossy(&data[offset..offset + line_end]).into_owned();
+        offset += line_end + 1;
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(header_error("not a PLY file".to_string()));
+            }
+            continue;
+        }
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(header_error(format!("unknown format {}", name))),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| header_error(format!("invalid element count {}", count)))?;
+                elements.push(Element { name: name.to_string(), line: line_number, count, properties: Vec::new() });
+            }
+            ["property", "list", count_type, item_type, name] => {
+                let element = elements.last_mut().ok_or_else(|| header_error("property before the first element".to_string()))?;
+                let parse_type = |name: &str| ScalarType::parse(name).ok_or_else(|| header_error(format!("unknown type {}", name)));
+                element.properties.push(Property::List { name: name.to_string(), count_type: parse_type(count_type)?, item_type: parse_type(item_type)? });
+            }
+            ["property", scalar_type, name] => {
+                let element = elements.last_mut().ok_or_else(|| header_error("property before the first element".to_string()))?;
+                let scalar_type = ScalarType::parse(scalar_type).ok_or_else(|| header_error(format!("unknown type {}", scalar_type)))?;
+                element.properties.push(Property::Scalar { name: name.to_string(), scalar_type });
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(header_error(format!("invalid header line {}", line.trim()))),
+        }
+    }
+    let format = format.ok_or_else(|| PlyError::Header { path: path.to_path_buf(), line: line_number, reason: "header without format".to_string() })?;
+    if let Some(vertex) = elements.iter().find(|element| element.name == "vertex") {
+        if (0..3).any(|axis| !vertex.properties.iter().any(|property| Target::of(property.name()) == Target::Position(axis))) {
+            return Err(PlyError::Header { path: path.to_path_buf(), line: vertex.line, reason: "vertex element needs x, y and z properties".to_string() });
+        }
+    }
+    Ok((format, elements, offset))
+}
+
+/// Loads a PLY file, see `Mesh::load_ply`.
+pub(crate) fn load(path: &std::path::Path) -> Result<Mesh, PlyError> {
+    let data = std::fs::read(path).map_err(|source| PlyError::Io { path: path.to_path_buf(), source })?;
+    let (format, elements, body_offset) = read_header(path, &data)?;
+    let body = &data[body_offset..];
+    let text = if format == Format::Ascii { String::from_utf8_lossy(body) } else { std::borrow::Cow::Borrowed("") };
+    let mut reader = Reader::new(format, body, &text);
+
+    let mut verts = Vec::<Vertex>::new();
+    let mut faces = Vec::<Vec<f64>>::new();
+    let mut indices = Vec::<u32>::new();
+    let mut has_normals = false;
+    let mut has_uvs = false;
+    for element in &elements {
+        let data_error = |index: usize, reason: String| PlyError::Data { path: path.to_path_buf(), element: element.name.clone(), index, reason };
+        let targets = element.properties.iter().map(|property| Target::of(property.name())).collect::<Vec<_>>();
+        if element.name == "vertex" {
+            has_normals = (0..3).all(|axis| targets.contains(&Target::Normal(axis)));
+            has_uvs = (0..2).all(|axis| targets.contains(&Target::Uv(axis)));
+        }
+
+        for index in 0..element.count {
+            let mut vertex = Vertex::default();
+            let mut polygon = Vec::new();
+            for (property, target) in element.properties.iter().zip(&targets) {
+                match property {
+                    Property::Scalar { scalar_type, .. } => {
+                        let value = reader.read(*scalar_type).map_err(|reason| data_error(index, reason))?;
+                        match target {
+                            Target::Position(axis) => vertex.position[*axis] = value as f32,
+                            Target::Normal(axis) => vertex.normal[*axis] = value as f32,
+                            Target::Color(channel) => vertex.color[*channel] = (value / scalar_type.color_scale()) as f32,
+                            Target::Uv(axis) => vertex.uv[*axis] = value as f32,
+                            Target::Ignored => {}
+                        }
+                    }
+                    Property::List { name, count_type, item_type } => {
+                        let count = reader.read(*count_type).map_err(|reason| data_error(index, reason))?;
+                        for _ in 0..count as usize {
+                            let item = reader.read(*item_type).map_err(|reason| data_error(index, reason))?;
+                            if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                                polygon.push(item);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if element.name == "vertex" {
+                // Colors are stored sRGB encoded while the shader expects linear vertex colors
+                for channel in &mut vertex.color[..3] {
+                    *channel = texture::srgb_to_linear(*channel);
+                }
+                if has_uvs {
+                    vertex.uv = mesh::uv_from_bottom_left(vertex.uv);
+                }
+                verts.push(vertex);
+            } else if element.name == "face" {
+                if polygon.len() < 3 {
+                    return Err(data_error(index, format!("face with {} vertices", polygon.len())));
+                }
+                faces.push(polygon);
+            }
+        }
+    }
+
+    // Checked once everything is read, nothing requires the vertices to come before the faces
+    for (index, polygon) in faces.iter().enumerate() {
+        if let Some(vertex) = polygon.iter().find(|vertex| **vertex < 0.0 || **vertex as usize >= verts.len()) {
+            return Err(PlyError::Data { path: path.to_path_buf(), element: "face".to_string(), index, reason: format!("vertex {} is out of range for {} vertices", vertex, verts.len()) });
+        }
+        mesh::triangulate_fan(&polygon.iter().map(|vertex| *vertex as u32).collect::<Vec<_>>(), &mut indices);
+    }
+
+    // Scans without faces are point clouds, drawn as splats instead of triangles
+    let topology = if indices.is_empty() { Topology::Points } else { Topology::Triangles };
+    let mut indices = match topology {
+        Topology::Points => Indices::sequential(verts.len()),
+        Topology::Triangles => Indices::from_u32(indices),
+    };
+    if !has_normals && topology == Topology::Triangles {
+        verts = mesh::flat_shaded(&verts, &indices);
+        indices = Indices::sequential(verts.len());
+    }
+    if has_uvs && topology == Topology::Triangles {
+        mesh::generate_tangents(&mut verts, &indices);
+    }
+    Ok(Mesh {
+        submeshes: vec![Submesh {
+            index_range: 0..indices.len() as u32,
+            topology,
+            base_vertex: 0,
+            material: None,
+            variant_materials: Vec::new(),
+        }],
+        verts,
+        indices,
+        morph_targets: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `data` to a temporary file and loads it.
+    fn load_bytes(name: &str, data: &[u8]) -> Result<Mesh, PlyError> {
+        let path = std::env::temp_dir().join(format!("webgpu_renderer_ply_{}_{}.ply", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let mesh = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    const POSITIONS: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.5]];
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [128, 128, 128]];
+
+    /// The quad of `ASCII` in one of the binary formats, `to_bytes` turns little endian values into the file's byte order.
+    /// The face list count is a ushort so byte swapping covers one, two and four byte values.
+    fn binary(format: &str, to_bytes: fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\ncomment built by a test\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list ushort int vertex_indices\nend_header\n", format).into_bytes();
+        for (position, color) in POSITIONS.iter().zip(COLORS) {
+            for value in position {
+                data.extend(to_bytes(&value.to_le_bytes()));
+            }
+            data.extend(color);
+        }
+        data.extend(to_bytes(&4u16.to_le_bytes()));
+        for index in [0i32, 1, 2, 3] {
+            data.extend(to_bytes(&index.to_le_bytes()));
+        }
+        data
+    }
+
+    const ASCII: &[u8] = b"ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0.5 128 128 128
+4 0 1 2 3
+";
+
+    #[test]
+    fn ascii_and_binary_formats_decode_the_same() {
+        let ascii = load_bytes("ascii", ASCII).unwrap();
+        let little_endian = load_bytes("little_endian", &binary("binary_little_endian", |bytes| bytes.to_vec())).unwrap();
+        let big_endian = load_bytes("big_endian", &binary("binary_big_endian", |bytes| bytes.iter().rev().copied().collect())).unwrap();
+        for mesh in [&ascii, &little_endian, &big_endian] {
+            assert_eq!(bytemuck::cast_slice::<_, u8>(&mesh.verts), bytemuck::cast_slice::<_, u8>(&ascii.verts));
+            assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), ascii.indices.iter().collect::<Vec<_>>());
+        }
+
+        // Without normals the quad is flat shaded, so every corner of the two fan triangles gets its own vertex
+        assert_eq!(ascii.submeshes.len(), 1);
+        let corners = ascii.verts.iter().map(|vertex| vertex.position).collect::<Vec<_>>();
+        assert_eq!(corners, [0, 1, 2, 0, 2, 3].map(|index| POSITIONS[index]));
+        // uchar colors are divided by 255 and then decoded from sRGB, 128 / 255 is about 0.216 in linear
+        assert_eq!(ascii.verts[0].color, [1.0, 0.0, 0.0, 1.0]);
+        assert!((ascii.verts[5].color[0] - 0.2158605).abs() < 1e-5, "got {:?}", ascii.verts[5].color);
+    }
+
+    #[test]
+    fn files_without_faces_are_point_clouds() {
+        let mesh = load_bytes("points", b"ply
+format ascii 1.0
+element vertex 2
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+end_header
+1 2 3 0 1 0
+-1 -2 -3 1 0 0
+").unwrap();
+        assert!(mesh.is_point_cloud());
+        assert_eq!(mesh.verts.iter().map(|vertex| (vertex.position, vertex.normal)).collect::<Vec<_>>(), [([1.0, 2.0, 3.0], [0.0, 1.0, 0.0]), ([-1.0, -2.0, -3.0], [1.0, 0.0, 0.0])]);
+    }
+
+    #[test]
+    fn face_indices_must_reach_a_vertex() {
+        let ascii = String::from_utf8(ASCII.to_vec()).unwrap().replace("4 0 1 2 3", "3 0 1 4");
+        match load_bytes("out_of_range", ascii.as_bytes()) {
+            Err(PlyError::Data { element, index: 0, reason, .. }) => {
+                assert_eq!(element, "face");
+                assert_eq!(reason, "vertex 4 is out of range for 4 vertices");
+            }
+            other => panic!("expected a face error, got {:?}", other.map(|mesh| mesh.verts.len())),
+        }
+    }
+}
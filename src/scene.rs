@@ -0,0 +1,409 @@
+use cgmath::{InnerSpace, SquareMatrix};
+
+use crate::animation;
+use crate::camera;
+use crate::light;
+use crate::material;
+use crate::mesh;
+use crate::obj;
+use crate::skin;
+
+/// A glTF node, transforms are relative to the parent node.
+pub struct Node {
+    pub name: Option<String>,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    /// Skin the node's mesh is deformed by
+    pub skin: Option<usize>,
+    pub camera: Option<usize>,
+    /// KHR_lights_punctual light placed by the node
+    pub light: Option<usize>,
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    /// Morph target weights of the node's mesh
+    pub weights: Vec<f32>,
+    /// Set when the node was authored with a `matrix`, it is used instead of translation, rotation and scale
+    pub matrix: Option<cgmath::Matrix4<f32>>,
+    /// EXT_mesh_gpu_instancing, the node's mesh is drawn once per transform, each one applied before the node's own
+    pub instances: Vec<cgmath::Matrix4<f32>>,
+}
+
+impl Node {
+    fn from_gltf(node: &json::JsonValue, index: usize) -> Result<Self, mesh::GltfError> {
+        let object = format!("nodes[{}]", index);
+        // Column major, same as cgmath
+        let matrix = mesh::optional_f32_array::<16>(node, &object, "matrix")?.map(|m| cgmath::Matrix4::new(
+            m[0], m[1], m[2], m[3],
+            m[4], m[5], m[6], m[7],
+            m[8], m[9], m[10], m[11],
+            m[12], m[13], m[14], m[15],
+        ));
+        let translation = mesh::optional_f32_array::<3>(node, &object, "translation")?.unwrap_or([0., 0., 0.]);
+        // glTF stores quaternions as x, y, z, w
+        let [x, y, z, w] = mesh::optional_f32_array::<4>(node, &object, "rotation")?.unwrap_or([0., 0., 0., 1.]);
+        let scale = mesh::optional_f32_array::<3>(node, &object, "scale")?.unwrap_or([1., 1., 1.]);
+
+        let mut weights = Vec::new();
+        for weight in node["weights"].members() {
+            weights.push(weight.as_f32().ok_or_else(|| mesh::GltfError::MissingProperty { object: object.clone(), property: "weights" })?);
+        }
+
+        let mut children = Vec::new();
+        for child in node["children"].members() {
+            children.push(child.as_usize().ok_or_else(|| mesh::GltfError::MissingProperty { object: object.clone(), property: "children" })?);
+        }
+
+        Ok(Self {
+            name: node["name"].as_str().map(String::from),
+            children,
+            mesh: node["mesh"].as_usize(),
+            skin: node["skin"].as_usize(),
+            camera: node["camera"].as_usize(),
+            light: node["extensions"]["KHR_lights_punctual"]["light"].as_usize(),
+            translation: translation.into(),
+            rotation: cgmath::Quaternion::new(w, x, y, z),
+            scale: scale.into(),
+            weights,
+            matrix,
+            instances: Vec::new(),
+        })
+    }
+
+    /// Reads the EXT_mesh_gpu_instancing TRANSLATION, ROTATION and SCALE attributes of node `index` into instance transforms.
+    fn gpu_instances_from_gltf(gltf: &mesh::GltfDocument, index: usize) -> Result<Vec<cgmath::Matrix4<f32>>, mesh::GltfError> {
+        let attributes = &gltf.json["nodes"][index]["extensions"]["EXT_mesh_gpu_instancing"]["attributes"];
+        if !attributes.is_object() {
+            return Ok(Vec::new());
+        }
+        let translations = attributes["TRANSLATION"].as_usize().map(|accessor| mesh::read_accessor_f32::<3>(gltf, accessor)).transpose()?;
+        let rotations = attributes["ROTATION"].as_usize().map(|accessor| mesh::read_accessor_f32::<4>(gltf, accessor)).transpose()?;
+        let scales = attributes["SCALE"].as_usize().map(|accessor| mesh::read_accessor_f32::<3>(gltf, accessor)).transpose()?;
+
+        // Every attribute has one element per instance
+        let counts = [translations.as_ref().map(Vec::len), rotations.as_ref().map(Vec::len), scales.as_ref().map(Vec::len)];
+        let Some(count) = counts.iter().flatten().copied().next() else {
+            return Err(mesh::GltfError::Node { index, reason: "EXT_mesh_gpu_instancing has no TRANSLATION, ROTATION or SCALE".to_string() });
+        };
+        if counts.iter().flatten().any(|other| *other != count) {
+            return Err(mesh::GltfError::Node { index, reason: "EXT_mesh_gpu_instancing attributes have different counts".to_string() });
+        }
+
+        Ok((0..count).map(|i| {
+            let translation = translations.as_ref().map_or([0., 0., 0.], |translations| translations[i]);
+            // glTF stores quaternions as x, y, z, w
+            let [x, y, z, w] = rotations.as_ref().map_or([0., 0., 0., 1.], |rotations| rotations[i]);
+            let [sx, sy, sz] = scales.as_ref().map_or([1., 1., 1.], |scales| scales[i]);
+            cgmath::Matrix4::from_translation(translation.into())
+                * cgmath::Matrix4::from(cgmath::Quaternion::new(w, x, y, z).normalize())
+                * cgmath::Matrix4::from_nonuniform_scale(sx, sy, sz)
+        }).collect())
+    }
+
+    pub fn local_transform(&self) -> cgmath::Matrix4<f32> {
+        match self.matrix {
+            Some(matrix) => matrix,
+            None => cgmath::Matrix4::from_translation(self.translation)
+                * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z),
+        }
+    }
+}
+
+/// A mesh placed in the scene by a node.
+pub struct MeshInstance {
+    pub mesh: usize,
+    /// None for meshes shown without a node
+    pub node: Option<usize>,
+    /// Skinned instances are placed by their joints, their transform is the identity
+    pub skin: Option<usize>,
+    pub transform: cgmath::Matrix4<f32>,
+}
+
+/// A KHR_lights_punctual light placed in the scene by a node.
+pub struct LightInstance {
+    /// Index into `Scene::lights`
+    pub light: usize,
+    pub node: usize,
+    pub transform: cgmath::Matrix4<f32>,
+}
+
+#[derive(Default)]
+pub struct Scene {
+    pub meshes: Vec<mesh::Mesh>,
+    pub materials: Vec<material::Material>,
+    pub textures: Vec<material::ImageTexture>,
+    /// Decoded glTF images, indexed by `ImageTexture::image`
+    pub images: Vec<image::DynamicImage>,
+    pub skins: Vec<skin::Skin>,
+    pub animations: Vec<animation::Animation>,
+    pub cameras: Vec<camera::GltfCamera>,
+    pub lights: Vec<light::Light>,
+    pub nodes: Vec<Node>,
+    /// Root nodes of the scene that was loaded
+    pub roots: Vec<usize>,
+    /// Names of the KHR_materials_variants variants, `active_variant` indexes into this list
+    pub variants: Vec<String>,
+    /// Variant whose materials are drawn, None draws every submesh with its default material
+    pub active_variant: Option<usize>,
+    /// glTF camera the scene is viewed through, None views it through the orbit camera. Set with `select_camera`
+    pub active_camera: Option<usize>,
+}
+
+impl Scene {
+    /// A scene with a single node drawing `mesh`, for formats without a scene graph like STL.
+    pub fn from_mesh(mesh: mesh::Mesh) -> Self {
+        let node = Node {
+            name: None,
+            children: Vec::new(),
+            mesh: Some(0),
+            skin: None,
+            camera: None,
+            light: None,
+            translation: cgmath::vec3(0.0, 0.0, 0.0),
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: cgmath::vec3(1.0, 1.0, 1.0),
+            weights: Vec::new(),
+            matrix: None,
+            instances: Vec::new(),
+        };
+        Self {
+            meshes: vec![mesh],
+            nodes: vec![node],
+            roots: vec![0],
+            ..Default::default()
+        }
+    }
+
+    /// Loads a Wavefront OBJ file with its MTL materials as a scene with a single node.
+    pub fn load_obj(path: &std::path::Path) -> Result<Self, obj::ObjError> {
+        let obj = obj::ObjDocument::open(path)?;
+        Ok(Self {
+            materials: obj.materials,
+            textures: obj.textures,
+            images: obj.images,
+            ..Self::from_mesh(obj.mesh)
+        })
+    }
+
+    /// Loads the default scene of a glTF file, or the first scene if the file doesn't name one.
+    pub fn load_gltf(path: &std::path::Path) -> Result<Self, mesh::GltfError> {
+        let gltf = mesh::GltfDocument::open(path)?;
+
+        let meshes = (0..gltf.json["meshes"].len()).map(|i| mesh::Mesh::from_gltf(&gltf, i)).collect::<Result<Vec<_>, _>>()?;
+        let materials = material::Material::all_from_gltf(&gltf)?;
+        let textures = material::ImageTexture::all_from_gltf(&gltf)?;
+        for (index, material) in materials.iter().enumerate() {
+            if let Some(texture_ref) = material.texture_slots().into_iter().filter_map(|(texture_ref, _)| texture_ref).find(|texture_ref| texture_ref.texture >= textures.len()) {
+                return Err(mesh::GltfError::Material { index, reason: format!("texture {} does not exist", texture_ref.texture) });
+            }
+        }
+        let images = (0..gltf.json["images"].len()).map(|i| material::load_image(&gltf, i)).collect::<Result<Vec<_>, _>>()?;
+        let mut variants = Vec::new();
+        for (index, variant) in gltf.json["extensions"]["KHR_materials_variants"]["variants"].members().enumerate() {
+            let object = format!("extensions.KHR_materials_variants.variants[{}]", index);
+            variants.push(mesh::required_str(variant, &object, "name")?.to_string());
+        }
+        for (index, mesh) in meshes.iter().enumerate() {
+            if let Some(material) = mesh.submeshes.iter().filter_map(|submesh| submesh.material).find(|material| *material >= materials.len()) {
+                return Err(mesh::GltfError::Mesh { index, reason: format!("material {} does not exist", material) });
+            }
+            for (variant, material) in mesh.submeshes.iter().flat_map(|submesh| submesh.variant_materials.iter()) {
+                if *variant >= variants.len() {
+                    return Err(mesh::GltfError::Mesh { index, reason: format!("variant {} does not exist", variant) });
+                }
+                if *material >= materials.len() {
+                    return Err(mesh::GltfError::Mesh { index, reason: format!("variant material {} does not exist", material) });
+                }
+            }
+        }
+        let skins = (0..gltf.json["skins"].len()).map(|i| skin::Skin::from_gltf(&gltf, i)).collect::<Result<Vec<_>, _>>()?;
+        let cameras = (0..gltf.json["cameras"].len()).map(|i| camera::GltfCamera::from_gltf(&gltf, i)).collect::<Result<Vec<_>, _>>()?;
+        let lights = light::Light::all_from_gltf(&gltf)?;
+        let mut nodes = gltf.json["nodes"].members().enumerate().map(|(i, node)| Node::from_gltf(node, i)).collect::<Result<Vec<_>, _>>()?;
+        for (index, node) in nodes.iter_mut().enumerate() {
+            node.instances = Node::gpu_instances_from_gltf(&gltf, index)?;
+            // Nodes without weights of their own start out with the default weights of their mesh
+            if let (true, Some(mesh)) = (node.weights.is_empty(), node.mesh) {
+                node.weights = gltf.json["meshes"][mesh]["weights"].members().filter_map(|weight| weight.as_f32()).collect();
+            }
+        }
+        let animations = (0..gltf.json["animations"].len()).map(|i| animation::Animation::from_gltf(&gltf, i, nodes.len())).collect::<Result<Vec<_>, _>>()?;
+        for (index, node) in nodes.iter().enumerate() {
+            if let Some(child) = node.children.iter().find(|child| **child >= nodes.len()) {
+                return Err(mesh::GltfError::Node { index, reason: format!("child {} does not exist", child) });
+            }
+            if let Some(mesh) = node.mesh.filter(|mesh| *mesh >= meshes.len()) {
+                return Err(mesh::GltfError::Node { index, reason: format!("mesh {} does not exist", mesh) });
+            }
+            if let Some(camera) = node.camera.filter(|camera| *camera >= cameras.len()) {
+                return Err(mesh::GltfError::Node { index, reason: format!("camera {} does not exist", camera) });
+            }
+            if let Some(light) = node.light.filter(|light| *light >= lights.len()) {
+                return Err(mesh::GltfError::Node { index, reason: format!("light {} does not exist", light) });
+            }
+            if let Some(skin) = node.skin {
+                let joint_count = skins.get(skin).ok_or_else(|| mesh::GltfError::Node { index, reason: format!("skin {} does not exist", skin) })?.joints.len();
+                let mesh = node.mesh.ok_or_else(|| mesh::GltfError::Node { index, reason: "has a skin but no mesh".to_string() })?;
+                if let Some(joint) = meshes[mesh].verts.iter().flat_map(|vertex| vertex.joints).find(|joint| *joint as usize >= joint_count) {
+                    return Err(mesh::GltfError::Node { index, reason: format!("mesh {} uses joint {} but skin {} has {} joints", mesh, joint, skin, joint_count) });
+                }
+            }
+        }
+        for (index, skin) in skins.iter().enumerate() {
+            if let Some(joint) = skin.joints.iter().find(|joint| **joint >= nodes.len()) {
+                return Err(mesh::GltfError::Skin { index, reason: format!("joint node {} does not exist", joint) });
+            }
+        }
+
+        let (scene_index, roots) = if gltf.json["scenes"].is_empty() {
+            // Without scenes treat every node that isn't a child as a root
+            let mut is_child = vec![false; nodes.len()];
+            for child in nodes.iter().flat_map(|node| node.children.iter()) {
+                is_child[*child] = true;
+            }
+            (0, (0..nodes.len()).filter(|i| !is_child[*i]).collect())
+        } else {
+            let scene_index = gltf.json["scene"].as_usize().unwrap_or(0);
+            let scene = &gltf.json["scenes"][scene_index];
+            if !scene.is_object() {
+                return Err(mesh::GltfError::Scene { index: scene_index, reason: "does not exist".to_string() });
+            }
+            let mut roots = Vec::new();
+            for root in scene["nodes"].members() {
+                let root = root.as_usize().filter(|root| *root < nodes.len())
+                    .ok_or_else(|| mesh::GltfError::Scene { index: scene_index, reason: format!("root node {} does not exist", root) })?;
+                roots.push(root);
+            }
+            (scene_index, roots)
+        };
+
+        // The spec requires nodes to form disjoint trees, reject cycles and shared children so traversal terminates
+        let mut visited = vec![false; nodes.len()];
+        let mut stack = roots.clone();
+        while let Some(node_index) = stack.pop() {
+            if visited[node_index] {
+                return Err(mesh::GltfError::Scene { index: scene_index, reason: format!("node {} is reachable more than once", node_index) });
+            }
+            visited[node_index] = true;
+            stack.extend(nodes[node_index].children.iter().copied());
+        }
+
+        Ok(Self {
+            meshes,
+            materials,
+            textures,
+            images,
+            skins,
+            animations,
+            cameras,
+            lights,
+            nodes,
+            roots,
+            variants,
+            active_variant: None,
+            active_camera: None,
+        })
+    }
+
+    /// Makes the variant with the given name active, returns false and leaves the active variant alone if there is none.
+    pub fn select_variant(&mut self, name: &str) -> bool {
+        match self.variants.iter().position(|variant| variant == name) {
+            Some(variant) => {
+                self.active_variant = Some(variant);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Views the scene through glTF camera `camera`, or through the orbit camera for None.
+    /// Returns false and leaves the active camera alone if no node in the scene places `camera`.
+    pub fn select_camera(&mut self, camera: Option<usize>) -> bool {
+        if let Some(camera) = camera {
+            if self.camera(camera, 1.0).is_none() {
+                return false;
+            }
+        }
+        self.active_camera = camera;
+        true
+    }
+
+    /// World transform of every node, nodes outside of the scene are left as identity.
+    pub fn world_transforms(&self) -> Vec<cgmath::Matrix4<f32>> {
+        let mut world_transforms = vec![cgmath::Matrix4::identity(); self.nodes.len()];
+        // load_gltf made sure the nodes form trees, so every node is visited at most once
+        let mut stack = self.roots.iter().map(|root| (*root, cgmath::Matrix4::identity())).collect::<Vec<_>>();
+        while let Some((node_index, parent_transform)) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let world_transform = parent_transform * node.local_transform();
+            world_transforms[node_index] = world_transform;
+            stack.extend(node.children.iter().map(|child| (*child, world_transform)));
+        }
+        world_transforms
+    }
+
+    /// Every mesh placed in the scene with its world transform.
+    pub fn mesh_instances(&self) -> Vec<MeshInstance> {
+        if self.nodes.is_empty() {
+            // Files without nodes still get their meshes shown, at the origin
+            return (0..self.meshes.len()).map(|mesh| MeshInstance { mesh, node: None, skin: None, transform: cgmath::Matrix4::identity() }).collect();
+        }
+
+        let world_transforms = self.world_transforms();
+        let mut instances = Vec::new();
+        let mut stack = self.roots.clone();
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if let Some(mesh) = node.mesh {
+                // The spec has skinned meshes ignore the transform of their own node
+                let transform = if node.skin.is_some() { cgmath::Matrix4::identity() } else { world_transforms[node_index] };
+                if node.instances.is_empty() {
+                    instances.push(MeshInstance { mesh, node: Some(node_index), skin: node.skin, transform });
+                } else {
+                    instances.extend(node.instances.iter().map(|instance| MeshInstance { mesh, node: Some(node_index), skin: node.skin, transform: transform * instance }));
+                }
+            }
+            stack.extend(node.children.iter().copied());
+        }
+        instances
+    }
+
+    /// Every light placed in the scene with its world transform.
+    pub fn light_instances(&self) -> Vec<LightInstance> {
+        let world_transforms = self.world_transforms();
+        let mut instances = Vec::new();
+        let mut stack = self.roots.clone();
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if let Some(light) = node.light {
+                instances.push(LightInstance { light, node: node_index, transform: world_transforms[node_index] });
+            }
+            stack.extend(node.children.iter().copied());
+        }
+        instances
+    }
+
+    /// glTF camera `index` as placed by the first node in the scene that uses it, None if no such node exists.
+    /// `viewport_aspect_ratio` is used by cameras that don't specify an aspect ratio.
+    pub fn camera(&self, index: usize, viewport_aspect_ratio: f32) -> Option<camera::Camera> {
+        let gltf_camera = self.cameras.get(index)?;
+        let world_transforms = self.world_transforms();
+        // Depth first in file order, so "first" means the same thing for every caller
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.camera == Some(index) {
+                return Some(gltf_camera.to_camera(world_transforms[node_index], viewport_aspect_ratio));
+            }
+            stack.extend(node.children.iter().rev().copied());
+        }
+        None
+    }
+
+    /// Joint matrices of every skin for the current node transforms.
+    pub fn joint_matrices(&self) -> Vec<Vec<cgmath::Matrix4<f32>>> {
+        let world_transforms = self.world_transforms();
+        self.skins.iter().map(|skin| skin.joint_matrices(&world_transforms)).collect()
+    }
+}
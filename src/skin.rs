@@ -0,0 +1,231 @@
+use cgmath::{InnerSpace, SquareMatrix};
+
+use crate::mesh::{self, GltfError};
+
+/// A glTF skin, the joints a skinned mesh is deformed by.
+pub struct Skin {
+    pub name: Option<String>,
+    /// Node of each joint, the JOINTS_0 attribute indexes into this list
+    pub joints: Vec<usize>,
+    /// Per joint, takes the mesh from its bind pose into the space of the joint
+    pub inverse_bind_matrices: Vec<cgmath::Matrix4<f32>>,
+}
+
+impl Skin {
+    pub(crate) fn from_gltf(gltf: &mesh::GltfDocument, index: usize) -> Result<Self, GltfError> {
+        let skin = &gltf.json["skins"][index];
+        let object = format!("skins[{}]", index);
+
+        let mut joints = Vec::new();
+        for joint in skin["joints"].members() {
+            joints.push(joint.as_usize().ok_or_else(|| GltfError::MissingProperty { object: object.clone(), property: "joints" })?);
+        }
+        if joints.is_empty() {
+            return Err(GltfError::MissingProperty { object, property: "joints" });
+        }
+
+        let inverse_bind_matrices = match skin["inverseBindMatrices"].as_usize() {
+            Some(accessor_index) => {
+                let matrices = mesh::read_accessor_f32::<16>(gltf, accessor_index)?;
+                if matrices.len() < joints.len() {
+                    return Err(GltfError::Accessor { index: accessor_index, reason: format!("has {} inverse bind matrices for {} joints", matrices.len(), joints.len()) });
+                }
+                // Column major, same as cgmath
+                matrices.into_iter().take(joints.len()).map(|m| cgmath::Matrix4::new(
+                    m[0], m[1], m[2], m[3],
+                    m[4], m[5], m[6], m[7],
+                    m[8], m[9], m[10], m[11],
+                    m[12], m[13], m[14], m[15],
+                )).collect()
+            }
+            // Without inverse bind matrices the joints are bound at the identity
+            None => vec![cgmath::Matrix4::identity(); joints.len()],
+        };
+
+        Ok(Self {
+            name: skin["name"].as_str().map(String::from),
+            joints,
+            inverse_bind_matrices,
+        })
+    }
+
+    /// The matrix of each joint for the given node world transforms, they take bind pose vertices straight to world space.
+    pub fn joint_matrices(&self, world_transforms: &[cgmath::Matrix4<f32>]) -> Vec<cgmath::Matrix4<f32>> {
+        self.joints.iter().zip(&self.inverse_bind_matrices)
+            .map(|(joint, inverse_bind_matrix)| world_transforms[*joint] * inverse_bind_matrix)
+            .collect()
+    }
+}
+
+/// Weighted sum of the joint matrices that move a vertex, identity for vertices without weights.
+fn skin_matrix(vertex: &mesh::Vertex, joint_matrices: &[cgmath::Matrix4<f32>]) -> cgmath::Matrix4<f32> {
+    if vertex.weights.iter().all(|weight| *weight == 0.0) {
+        return cgmath::Matrix4::identity();
+    }
+    vertex.joints.iter().zip(vertex.weights)
+        .map(|(joint, weight)| joint_matrices[*joint as usize] * weight)
+        .fold(cgmath::Matrix4::from_scale(0.0), |sum, matrix| sum + matrix)
+}
+
+/// Deforms vertices on the CPU the same way `vs_skinned` does on the GPU.
+pub fn skin_vertices(verts: &[mesh::Vertex], joint_matrices: &[cgmath::Matrix4<f32>]) -> Vec<mesh::Vertex> {
+    verts.iter().map(|vertex| {
+        let matrix = skin_matrix(vertex, joint_matrices);
+        let position = matrix * cgmath::Vector3::from(vertex.position).extend(1.0);
+        // Only correct for uniform scale, same as the shader
+        let normal = (matrix * cgmath::Vector3::from(vertex.normal).extend(0.0)).truncate();
+        let [x, y, z, w] = vertex.tangent;
+        let tangent = (matrix * cgmath::vec4(x, y, z, 0.0)).truncate();
+        mesh::Vertex {
+            position: position.truncate().into(),
+            normal: if normal.magnitude2() > 0.0 { normal.normalize().into() } else { vertex.normal },
+            tangent: if tangent.magnitude2() > 0.0 { tangent.normalize().extend(w).into() } else { vertex.tangent },
+            ..*vertex
+        }
+    }).collect()
+}
+
+/// Joint matrices of every skin, packed into one storage buffer and bound with a dynamic offset per skin.
+pub struct JointPalette {
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    /// Dynamic offset of each palette, in the order they were passed to `new`
+    pub offsets: Vec<u32>,
+}
+
+impl JointPalette {
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Joint palette bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, palettes: &[Vec<cgmath::Matrix4<f32>>]) -> Self {
+        let (data, offsets, binding_size) = Self::pack(device, palettes);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joint palette buffer"),
+            size: data.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true,
+        });
+        buffer.slice(..).get_mapped_range_mut().copy_from_slice(&data);
+        buffer.unmap();
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Joint palette bind group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(binding_size),
+                }),
+            }],
+        });
+
+        Self { buffer, bind_group, offsets }
+    }
+
+    /// Writes new joint matrices, the palettes must have the same lengths as the ones the palette was created with.
+    pub fn update(&self, device: &wgpu::Device, queue: &wgpu::Queue, palettes: &[Vec<cgmath::Matrix4<f32>>]) {
+        let (data, _, _) = Self::pack(device, palettes);
+        queue.write_buffer(&self.buffer, 0, &data);
+    }
+
+    fn pack(device: &wgpu::Device, palettes: &[Vec<cgmath::Matrix4<f32>>]) -> (Vec<u8>, Vec<u32>, u64) {
+        let palettes = palettes.iter()
+            .map(|palette| palette.iter().map(|matrix| -> [[f32; 4]; 4] { (*matrix).into() }).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        pack_dynamic(device, &palettes)
+    }
+}
+
+/// Lays out slices at offsets the device can bind as dynamic storage buffer offsets, returning the data,
+/// the offset of each slice and the binding size, which is large enough for the longest slice.
+pub(crate) fn pack_dynamic<T: bytemuck::Pod>(device: &wgpu::Device, slices: &[Vec<T>]) -> (Vec<u8>, Vec<u32>, u64) {
+    let element_size = std::mem::size_of::<T>() as u64;
+    let alignment = device.limits().min_storage_buffer_offset_alignment as u64;
+    let binding_size = slices.iter().map(Vec::len).max().unwrap_or(0).max(1) as u64 * element_size;
+    let stride = binding_size.div_ceil(alignment) * alignment;
+
+    let mut data = vec![0u8; (stride * slices.len().saturating_sub(1) as u64 + binding_size) as usize];
+    let mut offsets = Vec::with_capacity(slices.len());
+    for (i, slice) in slices.iter().enumerate() {
+        let offset = stride * i as u64;
+        let bytes = bytemuck::cast_slice(slice);
+        data[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+        offsets.push(offset as u32);
+    }
+    (data, offsets, binding_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    fn vertex(joints: [u32; 4], weights: [f32; 4]) -> mesh::Vertex {
+        mesh::Vertex {
+            position: [1.0, 2.0, 3.0],
+            normal: [1.0, 0.0, 0.0],
+            joints,
+            weights,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identity_palette_keeps_vertices() {
+        let palette = [cgmath::Matrix4::identity(), cgmath::Matrix4::identity()];
+        let skinned = skin_vertices(&[vertex([0, 1, 0, 0], [0.25, 0.75, 0.0, 0.0])], &palette);
+        assert_near(skinned[0].position, [1.0, 2.0, 3.0]);
+        assert_near(skinned[0].normal, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn translated_joint_moves_position_only() {
+        let palette = [cgmath::Matrix4::identity(), cgmath::Matrix4::from_translation(cgmath::vec3(10.0, 0.0, -1.0))];
+        let skinned = skin_vertices(&[vertex([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0])], &palette);
+        assert_near(skinned[0].position, [11.0, 2.0, 2.0]);
+        assert_near(skinned[0].normal, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn two_joints_blend_evenly() {
+        let palette = [
+            cgmath::Matrix4::from_translation(cgmath::vec3(2.0, 0.0, 0.0)),
+            cgmath::Matrix4::from_translation(cgmath::vec3(0.0, 4.0, 0.0)) * cgmath::Matrix4::from_angle_z(cgmath::Deg(90.0)),
+        ];
+        let skinned = skin_vertices(&[vertex([0, 1, 0, 0], [0.5, 0.5, 0.0, 0.0])], &palette);
+        // (3, 2, 3) from the first joint and (-2, 5, 3) from the second
+        assert_near(skinned[0].position, [0.5, 3.5, 3.0]);
+        // Halfway between +x and +y, renormalized
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_near(skinned[0].normal, [half, half, 0.0]);
+    }
+
+    #[test]
+    fn zero_weights_leave_vertex_in_place() {
+        // A vertex without weights isn't skinned even if its joints point at a transformed joint
+        let palette = [cgmath::Matrix4::from_translation(cgmath::vec3(5.0, 5.0, 5.0))];
+        let skinned = skin_vertices(&[vertex([0, 0, 0, 0], [0.0; 4])], &palette);
+        assert_near(skinned[0].position, [1.0, 2.0, 3.0]);
+        assert_near(skinned[0].normal, [1.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,98 @@
+use wgpu::util::DeviceExt;
+
+use crate::mesh;
+
+/// Splat diameter in pixels
+const POINT_SIZE: f32 = 3.0;
+
+/// Laid out like the splat shader's `SplatSettings`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SplatSettings {
+    /// Surface size in pixels
+    viewport: [f32; 2],
+    /// Splat diameter in pixels
+    point_size: f32,
+    _padding: f32,
+}
+
+/// Point clouds are drawn as round splats of a fixed size on screen, one camera facing quad per point.
+/// Each mesh with point submeshes gets its own bind group that pairs its points with the shared settings.
+pub struct PointSplats {
+    buffer: wgpu::Buffer,
+    settings: SplatSettings,
+    /// Per `gpu_meshes` entry, None for meshes without point submeshes
+    pub bind_groups: Vec<Option<wgpu::BindGroup>>,
+}
+
+impl PointSplats {
+    /// Vertices the splat shader draws per point, two triangles
+    pub const VERTICES_PER_POINT: u32 = 6;
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Splat bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, gpu_meshes: &[mesh::GpuMesh], viewport: winit::dpi::PhysicalSize<u32>) -> Self {
+        let settings = SplatSettings {
+            viewport: [viewport.width as f32, viewport.height as f32],
+            point_size: POINT_SIZE,
+            _padding: 0.0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Splat settings buffer"),
+            contents: bytemuck::cast_slice(&[settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_groups = gpu_meshes.iter().map(|gpu_mesh| {
+            let splat_buffer = gpu_mesh.splat_buffer.as_ref()?;
+            Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Splat bind group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: splat_buffer.as_entire_binding(),
+                    },
+                ],
+            }))
+        }).collect();
+
+        Self { buffer, settings, bind_groups }
+    }
+
+    /// Keeps the splats at their size in pixels when the surface is resized.
+    pub fn resize(&mut self, queue: &wgpu::Queue, viewport: winit::dpi::PhysicalSize<u32>) {
+        self.settings.viewport = [viewport.width as f32, viewport.height as f32];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.settings]));
+    }
+}
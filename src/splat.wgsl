@@ -0,0 +1,83 @@
+// Point clouds, drawn as round splats that keep their size in pixels
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+};
+
+@group(1)@binding(0)
+var<uniform> camera: CameraUniform;
+
+// Laid out by splat::SplatSettings
+struct SplatSettings {
+    viewport: vec2<f32>,
+    point_size: f32,
+};
+
+@group(0)@binding(0)
+var<uniform> settings: SplatSettings;
+
+// Laid out by mesh::GpuMesh's splat buffer, w of the position is always 1
+struct Splat {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+@group(0)@binding(1)
+var<storage, read> splats: array<Splat>;
+
+struct InstanceInput {
+    @location(8)  model_matrix_0: vec4<f32>,
+    @location(9)  model_matrix_1: vec4<f32>,
+    @location(10) model_matrix_2: vec4<f32>,
+    @location(11) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    // -1..1 across the splat's quad
+    @location(1) corner: vec2<f32>,
+};
+
+@vertex
+fn vs_splat(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Six vertices per splat, two triangles covering its quad
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0, -1.0),
+        vec2<f32>( 1.0,  1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>( 1.0,  1.0),
+        vec2<f32>(-1.0,  1.0),
+    );
+    let splat = splats[vertex_index / 6u];
+    let corner = corners[vertex_index % 6u];
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    let center = camera.view_proj * model_matrix * splat.position;
+    // Clip space spans two units across the viewport, scaling the offset by w undoes the perspective divide
+    let offset = corner * settings.point_size / settings.viewport * center.w;
+    var out: VertexOutput;
+    out.clip_position = center + vec4<f32>(offset, 0.0, 0.0);
+    out.color = splat.color;
+    out.corner = corner;
+    return out;
+}
+
+@fragment
+fn fs_splat(in: VertexOutput) -> @location(0) vec4<f32> {
+    if dot(in.corner, in.corner) > 1.0 {
+        discard;
+    }
+    // Scans are unlit, the colors already carry the lighting they were captured in
+    return in.color;
+}
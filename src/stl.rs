@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+
+use cgmath::InnerSpace;
+
+use crate::mesh::{Indices, Mesh, Submesh, Topology, Vertex};
+
+pub(crate) const BINARY_HEADER_LENGTH: usize = 80;
+/// Normal, three corners and the attribute byte count
+pub(crate) const BINARY_TRIANGLE_LENGTH: usize = 50;
+
+/// Why loading an STL file failed.
+#[derive(Debug)]
+pub enum StlError {
+    Io { path: std::path::PathBuf, source: std::io::Error },
+    /// A malformed statement of an ASCII file, `line` counts from 1
+    Parse { path: std::path::PathBuf, line: usize, reason: String },
+    /// A binary file whose size doesn't match its triangle count
+    InvalidSize { path: std::path::PathBuf, triangles: u32, size: usize },
+}
+
+impl std::fmt::Display for StlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StlError::Io { path, source } => write!(f, "failed to read {}: {}", path.display(), source),
+            StlError::Parse { path, line, reason } => write!(f, "{}:{}: {}", path.display(), line, reason),
+            StlError::InvalidSize { path, triangles, size } => write!(f, "{} is {} bytes, which doesn't match its {} triangles", path.display(), size, triangles),
+        }
+    }
+}
+
+impl std::error::Error for StlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StlError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A triangle with the facet normal the file stores for it.
+struct Facet {
+    normal: [f32; 3],
+    corners: [[f32; 3]; 3],
+}
+
+/// Reads the facets of an STL file, one list per solid. Binary files always hold a single solid.
+fn read_solids(path: &std::path::Path) -> Result<Vec<Vec<Facet>>, StlError> {
+    let data = std::fs::read(path).map_err(|source| StlError::Io { path: path.to_path_buf(), source })?;
+    // Some binary exporters start their header with "solid" too, a size matching the triangle count settles it.
+    // Text never contains NUL bytes while the high byte of the binary triangle count almost always is one.
+    let binary_size = (data.len() >= BINARY_HEADER_LENGTH + 4).then(|| {
+        let triangles = u32::from_le_bytes(data[BINARY_HEADER_LENGTH..BINARY_HEADER_LENGTH + 4].try_into().unwrap());
+        (triangles, BINARY_HEADER_LENGTH + 4 + triangles as usize * BINARY_TRIANGLE_LENGTH)
+    });
+    match binary_size {
+        Some((_, size)) if size == data.len() => Ok(vec![read_binary(&data)]),
+        _ if data.trim_ascii_start().starts_with(b"solid") && !data.contains(&0) => read_ascii(path, &String::from_utf8_lossy(&data)),
+        Some((triangles, _)) => Err(StlError::InvalidSize { path: path.to_path_buf(), triangles, size: data.len() }),
+        None => Err(StlError::InvalidSize { path: path.to_path_buf(), triangles: 0, size: data.len() }),
+    }
+}
+
+fn read_binary(data: &[u8]) -> Vec<Facet> {
+    // The attribute byte count at the end of each triangle is ignored, its color encodings were never standardized
+    data[BINARY_HEADER_LENGTH + 4..].chunks_exact(BINARY_TRIANGLE_LENGTH).map(|triangle| {
+        let floats = |offset: usize| -> [f32; 3] {
+            [0, 1, 2].map(|i| f32::from_le_bytes(triangle[offset + i * 4..offset + i * 4 + 4].try_into().unwrap()))
+        };
+        Facet {
+            normal: floats(0),
+            corners: [floats(12), floats(24), floats(36)],
+        }
+    }).collect()
+}
+
+fn read_ascii(path: &std::path::Path, text: &str) -> Result<Vec<Vec<Facet>>, StlError> {
+    let mut solids = Vec::new();
+    let mut facets = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut corners = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let parse_error = |reason: String| StlError::Parse { path: path.to_path_buf(), line: i + 1, reason };
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let floats = |values: &[&str]| -> Result<[f32; 3], StlError> {
+            if values.len() < 3 {
+                return Err(parse_error("expected 3 numbers".to_string()));
+            }
+            let mut parsed = [0.0; 3];
+            for (value, token) in parsed.iter_mut().zip(values) {
+                *value = token.parse().map_err(|_| parse_error(format!("invalid number {}", token)))?;
+            }
+            Ok(parsed)
+        };
+        match tokens.first().copied() {
+            // "facet normal nx ny nz"
+            Some("facet") => {
+                normal = if tokens.get(1) == Some(&"normal") { floats(&tokens[2..])? } else { [0.0; 3] };
+                corners.clear();
+            }
+            Some("vertex") => corners.push(floats(&tokens[1..])?),
+            Some("endfacet") => {
+                if corners.len() < 3 {
+                    return Err(parse_error(format!("facet with {} vertices", corners.len())));
+                }
+                // Facets are meant to be triangles, anything bigger is treated as a convex polygon
+                for i in 1..corners.len() - 1 {
+                    facets.push(Facet { normal, corners: [corners[0], corners[i], corners[i + 1]] });
+                }
+                corners.clear();
+            }
+            Some("endsolid") => solids.push(std::mem::take(&mut facets)),
+            // solid, outer loop and endloop carry nothing we need
+            _ => {}
+        }
+    }
+    // Tolerate files that end without endsolid
+    if !facets.is_empty() || solids.is_empty() {
+        solids.push(facets);
+    }
+    Ok(solids)
+}
+
+/// The normal implied by the counter-clockwise winding, falling back to the stored one for degenerate triangles.
+fn facet_normal(facet: &Facet) -> cgmath::Vector3<f32> {
+    let [p0, p1, p2] = facet.corners.map(cgmath::Vector3::from);
+    let normal = (p1 - p0).cross(p2 - p0);
+    if normal.magnitude2() > 0.0 {
+        normal.normalize()
+    } else if cgmath::Vector3::from(facet.normal).magnitude2() > 0.0 {
+        cgmath::Vector3::from(facet.normal).normalize()
+    } else {
+        cgmath::vec3(0.0, 0.0, 1.0)
+    }
+}
+
+/// Loads an STL file, see `Mesh::load_stl`.
+pub(crate) fn load(path: &std::path::Path, weld: bool) -> Result<Mesh, StlError> {
+    let solids = read_solids(path)?;
+    let mut verts = Vec::<Vertex>::new();
+    let mut indices = Vec::<u32>::new();
+    let mut submeshes = Vec::new();
+    // Accumulated unnormalized facet normals of each welded vertex, their length is twice the facet's area
+    let mut weld_normals = Vec::<cgmath::Vector3<f32>>::new();
+    let mut weld_lookup = HashMap::<[u32; 3], u32>::new();
+
+    for facets in solids {
+        let index_start = indices.len() as u32;
+        for facet in &facets {
+            if weld {
+                let [p0, p1, p2] = facet.corners.map(cgmath::Vector3::from);
+                let weighted_normal = (p1 - p0).cross(p2 - p0);
+                for corner in facet.corners {
+                    // Adding zero turns -0.0 into 0.0 so both weld together
+                    let key = corner.map(|component| (component + 0.0).to_bits());
+                    let index = *weld_lookup.entry(key).or_insert_with(|| {
+                        verts.push(Vertex { position: corner, ..Default::default() });
+                        weld_normals.push(cgmath::vec3(0.0, 0.0, 0.0));
+                        verts.len() as u32 - 1
+                    });
+                    weld_normals[index as usize] += weighted_normal;
+                    indices.push(index);
+                }
+            } else {
+                let normal = facet_normal(facet).into();
+                for corner in facet.corners {
+                    verts.push(Vertex { position: corner, normal, ..Default::default() });
+                    indices.push(verts.len() as u32 - 1);
+                }
+            }
+        }
+        if indices.len() as u32 > index_start {
+            submeshes.push(Submesh {
+                index_range: index_start..indices.len() as u32,
+                topology: Topology::Triangles,
+                base_vertex: 0,
+                material: None,
+                variant_materials: Vec::new(),
+            });
+        }
+    }
+    for (vertex, normal) in verts.iter_mut().zip(weld_normals) {
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+
+    let indices = if weld { Indices::from_u32(indices) } else { Indices::sequential(verts.len()) };
+    Ok(Mesh {
+        verts,
+        indices,
+        submeshes,
+        morph_targets: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `data` to a temporary file and loads it.
+    fn load_bytes(name: &str, data: &[u8], weld: bool) -> Result<Mesh, StlError> {
+        let path = std::env::temp_dir().join(format!("webgpu_renderer_stl_{}_{}.stl", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let mesh = load(&path, weld);
+        std::fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    fn binary(header: &[u8], triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(BINARY_HEADER_LENGTH, 0);
+        data.extend((triangles.len() as u32).to_le_bytes());
+        for corners in triangles {
+            // A zero normal, the loader recomputes it from the winding
+            for value in [[0.0; 3]].iter().chain(corners).flatten() {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(0u16.to_le_bytes());
+        }
+        data
+    }
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    #[test]
+    fn ascii_solids_become_submeshes() {
+        let mesh = load_bytes("ascii", b"solid first
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid first
+solid second
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 0 0 1
+      vertex 1 0 0
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 0 1 0
+      vertex 0 0 1
+    endloop
+  endfacet
+endsolid second
+", false).unwrap();
+        let ranges = mesh.submeshes.iter().map(|submesh| submesh.index_range.clone()).collect::<Vec<_>>();
+        assert_eq!(ranges, [0..3, 3..9]);
+        // Unwelded facets keep their own flat normals
+        assert_eq!(mesh.verts.len(), 9);
+        assert_near(mesh.verts[0].normal, [0.0, 0.0, 1.0]);
+        assert_near(mesh.verts[3].normal, [0.0, 1.0, 0.0]);
+        assert_near(mesh.verts[6].normal, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn binary_header_starting_with_solid_is_binary() {
+        let mesh = load_bytes("solid_header", &binary(b"solid exported by a careless tool", &[TRIANGLE]), false).unwrap();
+        assert_eq!(mesh.verts.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), TRIANGLE);
+        assert_near(mesh.verts[0].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn binary_size_must_match_triangle_count() {
+        let mut data = binary(b"binary", &[TRIANGLE]);
+        data.truncate(data.len() - 1);
+        assert!(matches!(load_bytes("truncated", &data, false), Err(StlError::InvalidSize { triangles: 1, .. })));
+        // Starting with "solid" doesn't make it text when it has NUL bytes
+        let mut data = binary(b"solid", &[TRIANGLE]);
+        data.push(0);
+        assert!(matches!(load_bytes("solid_nul", &data, false), Err(StlError::InvalidSize { triangles: 1, .. })));
+    }
+
+    #[test]
+    fn welding_joins_signed_zeros_and_weights_normals_by_area() {
+        // A triangle of area 2 facing +z and one of area 1 facing -x, sharing the edge from the origin to (0, 2, 0)
+        let mesh = load_bytes("weld", &binary(b"binary", &[
+            [[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]],
+            [[0.0, 2.0, 0.0], [-0.0, 0.0, -0.0], [0.0, 0.0, 1.0]],
+        ]), true).unwrap();
+        assert_eq!(mesh.verts.len(), 4);
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), [0, 1, 2, 2, 0, 3]);
+        // (0, 0, 4) + (-2, 0, 0) normalized, an unweighted average would be 45 degrees
+        let shared = [-1.0 / 5.0f32.sqrt(), 0.0, 2.0 / 5.0f32.sqrt()];
+        assert_near(mesh.verts[0].normal, shared);
+        assert_near(mesh.verts[2].normal, shared);
+        assert_near(mesh.verts[1].normal, [0.0, 0.0, 1.0]);
+        assert_near(mesh.verts[3].normal, [-1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn saved_files_load_back() {
+        let original = load_bytes("original", &binary(b"binary", &[TRIANGLE, [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]]]), true).unwrap();
+        let path = std::env::temp_dir().join(format!("webgpu_renderer_stl_saved_{}.stl", std::process::id()));
+        crate::export::save_stl(&original, &path).unwrap();
+        let reloaded = load(&path, false);
+        std::fs::remove_file(&path).unwrap();
+        let reloaded = reloaded.unwrap();
+
+        let corners = original.indices.iter().map(|index| original.verts[index as usize].position).collect::<Vec<_>>();
+        assert_eq!(reloaded.verts.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), corners);
+        assert_near(reloaded.verts[0].normal, [0.0, 0.0, 1.0]);
+        assert_near(reloaded.verts[3].normal, [0.0, 1.0, 0.0]);
+    }
+}
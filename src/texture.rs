@@ -1,6 +1,16 @@
 use image::GenericImageView;
 use anyhow::*;
 
+/// Decodes an sRGB encoded channel in 0..1.
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Encodes a linear channel in 0..1 as sRGB.
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub sampler: wgpu::Sampler,
@@ -47,27 +57,152 @@ impl Texture {
         Self { texture, sampler, view, }
     }
 
+    /// Same size and format as the surface, the surface is copied into it once the opaque scene has been drawn.
+    pub fn create_scene_color_texture(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: surface_config.width,
+                height: surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: surface_config.format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self { texture, sampler, view, }
+    }
+
     pub fn load_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         image: &image::DynamicImage,
         label: Option<&str>
     ) -> Result<Self> {
-        let rgba = image.to_rgba8();
+        let sampler = wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        };
+        Ok(Self::from_image(device, queue, image, label, true, &sampler))
+    }
+
+    /// Uploads an image with a full mip chain, `srgb` picks between color data and linear data such as normal maps.
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::DynamicImage,
+        label: Option<&str>,
+        srgb: bool,
+        sampler: &wgpu::SamplerDescriptor,
+    ) -> Self {
+        let mut rgba = image.to_rgba8();
+        // Mips are filtered in linear space, averaging sRGB encoded values would darken them
+        let decode = |channel: usize, value: u8| {
+            let value = value as f32 / 255.0;
+            if srgb && channel < 3 { srgb_to_linear(value) } else { value }
+        };
+        let mut linear = image::Rgba32FImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let pixel = rgba.get_pixel(x, y).0;
+            image::Rgba([0, 1, 2, 3].map(|channel| decode(channel, pixel[channel])))
+        });
         let image_size = image.dimensions();
         let texture_size = wgpu::Extent3d {
             width: image_size.0,
             height: image_size.1,
             depth_or_array_layers: 1,
         };
+        let mip_level_count = texture_size.max_mips(wgpu::TextureDimension::D2);
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label,
                 size: texture_size,
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: if srgb { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm },
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            }
+        );
+
+        for mip_level in 0..mip_level_count {
+            let mip_size = texture_size.mip_level_size(mip_level, wgpu::TextureDimension::D2);
+            if mip_level > 0 {
+                // Each level is filtered down from the previous one
+                linear = image::imageops::resize(&linear, mip_size.width, mip_size.height, image::imageops::FilterType::Triangle);
+                rgba = image::RgbaImage::from_fn(mip_size.width, mip_size.height, |x, y| {
+                    let pixel = linear.get_pixel(x, y).0;
+                    image::Rgba([0, 1, 2, 3].map(|channel| {
+                        let value = pixel[channel].clamp(0.0, 1.0);
+                        let value = if srgb && channel < 3 { linear_to_srgb(value) } else { value };
+                        (value * 255.0).round() as u8
+                    }))
+                });
+            }
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * mip_size.width),
+                    rows_per_image: Some(mip_size.height),
+                },
+                mip_size,
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(sampler);
+
+        Self { texture, sampler, view }
+    }
+
+    /// A 1x1 linear texture, used as a stand-in for texture slots that have nothing bound.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: [u8; 4],
+        label: &str
+    ) -> Self {
+        let texture_size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: texture_size,
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format: wgpu::TextureFormat::Rgba8Unorm,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[],
             }
@@ -83,26 +218,16 @@ impl Texture {
             &rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * image_size.0),
-                rows_per_image: Some(image_size.1),
+                bytes_per_row: Some(4),
+                rows_per_image: Some(1),
             },
             texture_size,
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor { 
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
 
-        Ok(Self {texture, sampler, view })
+        Self { texture, sampler, view }
     }
 
     pub fn from_memory(
@@ -111,7 +236,7 @@ impl Texture {
         data: &[u8],
         label: &str
     ) -> Result<Self> {
-        let img = image::load_from_memory(&data)?;
-        Self::load_image(&device, &queue, &img, Some(label))
+        let img = image::load_from_memory(data)?;
+        Self::load_image(device, queue, &img, Some(label))
     }
 }
\ No newline at end of file